@@ -0,0 +1,665 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, stderr, stdin, stdout, BufRead, Cursor, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// How [`IoBackend::open`] opens a file, chosen by the MODE of the `OPEN` opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// An existing file, for reading.
+    Read,
+    /// For writing, creating the file if needed and emptying it if it exists.
+    Write,
+    /// For writing at the end, creating the file if needed.
+    Append,
+    /// A new file for writing. It is an error if the file already exists.
+    Create,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    /// Anything else, such as a device or a socket.
+    Other,
+}
+
+/// What [`IoBackend::metadata`] knows about a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub kind: FileKind,
+    /// The size in bytes. Directories may report zero.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub readonly: bool,
+}
+
+/// A file opened by an [`IoBackend`].
+pub trait FileHandle: Read + Write {}
+
+impl<T: Read + Write> FileHandle for T {}
+
+/// Where the standard streams, files and environment of a VM lead. Every opcode that reads or
+/// writes goes through the backend of its VM, so embedders can capture output, feed input, or
+/// sandbox scripts by choosing one.
+///
+/// Buffering of stdout is done by the VM, so backends should pass writes on as they arrive.
+pub trait IoBackend {
+    fn write_stdout(&mut self, bytes: &[u8]) -> io::Result<()>;
+    fn write_stderr(&mut self, bytes: &[u8]) -> io::Result<()>;
+    fn stdin(&mut self) -> io::Result<Box<dyn BufRead + '_>>;
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>>;
+    /// The value of the environment variable `name`, if it is set.
+    fn env(&mut self, name: &str) -> io::Result<Option<String>>;
+    /// The names of the entries in the directory at `path`, sorted.
+    fn list_dir(&mut self, path: &Path) -> io::Result<Vec<String>>;
+    /// What is at `path`, or `None` if nothing is.
+    fn metadata(&mut self, path: &Path) -> io::Result<Option<FileInfo>>;
+    /// Creates the directory at `path`, whose parent must already exist.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+    /// Removes the empty directory at `path`.
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()>;
+    /// Moves the file or directory at `from` to `to`.
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// The absolute path that `path` leads to once symlinks are followed, which fails if nothing
+    /// is there. Backends without symlinks return the path as it is.
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// The standard streams and the filesystem of the process.
+#[derive(Debug, Default)]
+pub struct RealIo;
+
+impl IoBackend for RealIo {
+    fn write_stdout(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut out = stdout().lock();
+
+        out.write_all(bytes)?;
+        out.flush()
+    }
+
+    fn write_stderr(&mut self, bytes: &[u8]) -> io::Result<()> {
+        stderr().write_all(bytes)
+    }
+
+    fn stdin(&mut self) -> io::Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(stdin().lock()))
+    }
+
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+        let mut options = OpenOptions::new();
+
+        match mode {
+            OpenMode::Read => options.read(true),
+            OpenMode::Write => options.write(true).create(true).truncate(true),
+            OpenMode::Append => options.append(true).create(true),
+            OpenMode::Create => options.write(true).create_new(true),
+        };
+
+        Ok(Box::new(options.open(path)?))
+    }
+
+    fn env(&mut self, name: &str) -> io::Result<Option<String>> {
+        Ok(std::env::var(name).ok())
+    }
+
+    fn list_dir(&mut self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = fs::read_dir(path)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn metadata(&mut self, path: &Path) -> io::Result<Option<FileInfo>> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let kind = if metadata.is_file() {
+            FileKind::File
+        } else if metadata.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::Other
+        };
+
+        Ok(Some(FileInfo {
+            kind,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+        }))
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+#[derive(Default)]
+struct Memory {
+    stdin: Cursor<Vec<u8>>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    files: HashMap<PathBuf, Rc<RefCell<Vec<u8>>>>,
+    /// Directories created explicitly. The parents of files and directories exist too.
+    dirs: HashSet<PathBuf>,
+    env: HashMap<String, String>,
+}
+
+impl Memory {
+    /// Every file and directory that was created explicitly.
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.keys().chain(self.dirs.iter())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.parent().is_none()
+            || self.dirs.contains(path)
+            || self.paths().any(|other| other != path && other.starts_with(path))
+    }
+}
+
+/// Standard streams, files and environment variables held in memory, for tests and for capturing
+/// what a script does.
+///
+/// Clones share their contents, so an embedder can keep a clone to read the output back after
+/// handing the backend to a VM.
+#[derive(Clone, Default)]
+pub struct MemoryIo {
+    memory: Rc<RefCell<Memory>>,
+}
+
+impl MemoryIo {
+    pub fn new() -> Self {
+        MemoryIo::default()
+    }
+
+    /// Sets what reading from stdin returns.
+    pub fn with_stdin(self, input: impl Into<Vec<u8>>) -> Self {
+        self.memory.borrow_mut().stdin = Cursor::new(input.into());
+        self
+    }
+
+    /// Creates or replaces the file at `path`.
+    pub fn with_file(self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> Self {
+        self.memory
+            .borrow_mut()
+            .files
+            .insert(path.into(), Rc::new(RefCell::new(contents.into())));
+        self
+    }
+
+    /// Sets the environment variable `name`.
+    pub fn with_env(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.memory.borrow_mut().env.insert(name.into(), value.into());
+        self
+    }
+
+    /// Everything written to stdout so far.
+    pub fn stdout(&self) -> Vec<u8> {
+        self.memory.borrow().stdout.clone()
+    }
+
+    /// Everything written to stderr so far.
+    pub fn stderr(&self) -> Vec<u8> {
+        self.memory.borrow().stderr.clone()
+    }
+
+    /// The contents of the file at `path`, if it exists.
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.memory
+            .borrow()
+            .files
+            .get(path.as_ref())
+            .map(|file| file.borrow().clone())
+    }
+}
+
+/// A file held by [`MemoryIo`].
+struct MemoryFile {
+    data: Rc<RefCell<Vec<u8>>>,
+    pos: usize,
+    mode: OpenMode,
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.mode != OpenMode::Read {
+            return Err(io::Error::new(ErrorKind::Unsupported, "file is not open for reading"));
+        }
+
+        let data = self.data.borrow();
+        let count = buf.len().min(data.len().saturating_sub(self.pos));
+
+        buf[..count].copy_from_slice(&data[self.pos..self.pos + count]);
+        self.pos += count;
+
+        Ok(count)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mode == OpenMode::Read {
+            return Err(io::Error::new(ErrorKind::Unsupported, "file is not open for writing"));
+        }
+
+        let mut data = self.data.borrow_mut();
+
+        if self.mode == OpenMode::Append {
+            self.pos = data.len();
+        }
+
+        let overlap = buf.len().min(data.len().saturating_sub(self.pos));
+
+        data[self.pos..self.pos + overlap].copy_from_slice(&buf[..overlap]);
+        data.extend_from_slice(&buf[overlap..]);
+        self.pos += buf.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl IoBackend for MemoryIo {
+    fn write_stdout(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.memory.borrow_mut().stdout.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write_stderr(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.memory.borrow_mut().stderr.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn stdin(&mut self) -> io::Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(StdinReader(self.memory.borrow_mut())))
+    }
+
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+        let mut memory = self.memory.borrow_mut();
+
+        let data = match (memory.files.get(path), mode) {
+            (None, OpenMode::Read) => return Err(ErrorKind::NotFound.into()),
+            (Some(_), OpenMode::Create) => return Err(ErrorKind::AlreadyExists.into()),
+            (Some(data), OpenMode::Write) => {
+                data.borrow_mut().clear();
+                data.clone()
+            }
+            (Some(data), _) => data.clone(),
+            (None, _) => memory.files.entry(path.to_path_buf()).or_default().clone(),
+        };
+
+        Ok(Box::new(MemoryFile { data, pos: 0, mode }))
+    }
+
+    fn env(&mut self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.memory.borrow().env.get(name).cloned())
+    }
+
+    fn list_dir(&mut self, path: &Path) -> io::Result<Vec<String>> {
+        let memory = self.memory.borrow();
+
+        if memory.files.contains_key(path) {
+            return Err(ErrorKind::NotADirectory.into());
+        } else if !memory.is_dir(path) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let names: BTreeSet<String> = memory
+            .paths()
+            .filter_map(|other| other.strip_prefix(path).ok()?.components().next())
+            .map(|name| name.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        Ok(names.into_iter().collect())
+    }
+
+    fn metadata(&mut self, path: &Path) -> io::Result<Option<FileInfo>> {
+        let memory = self.memory.borrow();
+
+        let (kind, size) = match memory.files.get(path) {
+            Some(data) => (FileKind::File, data.borrow().len() as u64),
+            None if memory.is_dir(path) => (FileKind::Dir, 0),
+            None => return Ok(None),
+        };
+
+        Ok(Some(FileInfo {
+            kind,
+            size,
+            modified: None,
+            readonly: false,
+        }))
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let mut memory = self.memory.borrow_mut();
+        let parent = path.parent().unwrap_or(path);
+
+        if memory.files.contains_key(path) || memory.is_dir(path) {
+            return Err(ErrorKind::AlreadyExists.into());
+        } else if path.ancestors().any(|ancestor| memory.files.contains_key(ancestor)) {
+            return Err(ErrorKind::NotADirectory.into());
+        } else if !memory.is_dir(parent) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        memory.dirs.insert(path.to_path_buf());
+
+        Ok(())
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        let mut memory = self.memory.borrow_mut();
+
+        if memory.files.contains_key(path) {
+            return Err(ErrorKind::NotADirectory.into());
+        } else if !memory.is_dir(path) {
+            return Err(ErrorKind::NotFound.into());
+        } else if memory.paths().any(|other| other != path && other.starts_with(path)) {
+            return Err(ErrorKind::DirectoryNotEmpty.into());
+        }
+
+        memory.dirs.remove(path);
+
+        Ok(())
+    }
+
+    /// Behaves like [`fs::rename`] on Unix: a file replaces a file, a directory replaces an
+    /// empty directory, and anything beneath a directory moves with it.
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut memory = self.memory.borrow_mut();
+        let is_file = memory.files.contains_key(from);
+
+        if !is_file && !memory.is_dir(from) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        if from == to {
+            return Ok(());
+        }
+
+        if memory.files.contains_key(to) {
+            if !is_file {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+        } else if memory.is_dir(to) {
+            if is_file {
+                return Err(ErrorKind::IsADirectory.into());
+            } else if memory.paths().any(|other| other != to && other.starts_with(to)) {
+                return Err(ErrorKind::DirectoryNotEmpty.into());
+            }
+        }
+
+        if to.starts_with(from) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+
+        memory.files.remove(to);
+        memory.dirs.remove(to);
+
+        // Nothing is beneath `to` any more, so moved paths cannot land on ones that stay.
+        let moved = |path: &Path| match path.strip_prefix(from) {
+            Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rest) => to.join(rest),
+            Err(_) => path.to_path_buf(),
+        };
+        let files: Vec<_> =
+            memory.files.keys().filter(|path| path.starts_with(from)).cloned().collect();
+        let dirs: Vec<_> =
+            memory.dirs.iter().filter(|path| path.starts_with(from)).cloned().collect();
+
+        for path in files {
+            let data = memory.files.remove(&path).unwrap_or_default();
+            memory.files.insert(moved(&path), data);
+        }
+
+        for path in dirs {
+            memory.dirs.remove(&path);
+            memory.dirs.insert(moved(&path));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the stdin of a [`MemoryIo`] while holding its contents.
+struct StdinReader<'a>(std::cell::RefMut<'a, Memory>);
+
+impl Read for StdinReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.stdin.read(buf)
+    }
+}
+
+impl BufRead for StdinReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.stdin.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.stdin.consume(amount)
+    }
+}
+
+/// Refuses every read and write, for running scripts that should have no effects.
+#[derive(Debug, Default)]
+pub struct DenyIo;
+
+fn denied() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "denied by the I/O backend")
+}
+
+impl IoBackend for DenyIo {
+    fn write_stdout(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn write_stderr(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn stdin(&mut self) -> io::Result<Box<dyn BufRead + '_>> {
+        Err(denied())
+    }
+
+    fn open(&mut self, _path: &Path, _mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+        Err(denied())
+    }
+
+    fn env(&mut self, _name: &str) -> io::Result<Option<String>> {
+        Err(denied())
+    }
+
+    fn list_dir(&mut self, _path: &Path) -> io::Result<Vec<String>> {
+        Err(denied())
+    }
+
+    fn metadata(&mut self, _path: &Path) -> io::Result<Option<FileInfo>> {
+        Err(denied())
+    }
+
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn remove_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn rename(&mut self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(denied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes;
+    use crate::test_util::{jump, load, num, push, run, store, stdout, str, vm};
+    use crate::StdoutBuffering;
+
+    #[test]
+    fn print_and_write_are_captured() {
+        let io = MemoryIo::new();
+        let code = [
+            push(str("hi")),
+            vec![opcodes::PRINT],
+            push(num(0.)),
+            push(str("out")),
+            vec![opcodes::WRITE],
+            push(num(2.)),
+            push(str("err")),
+            vec![opcodes::WRITE],
+        ]
+        .concat();
+
+        assert_eq!(stdout(&io, &code), "hi\nout");
+        assert_eq!(io.stderr(), b"err");
+    }
+
+    #[test]
+    fn readln_reads_stdin_until_null() {
+        let io = MemoryIo::new().with_stdin("one\ntwo");
+        let code = [
+            jump(opcodes::MARKER, 0),
+            push(num(0.)),
+            push(num(1.)),
+            vec![opcodes::READLN, opcodes::DUP],
+            jump(opcodes::GOTO_IF_NULL, 1),
+            vec![opcodes::WRITE],
+            jump(opcodes::GOTO, 0),
+            jump(opcodes::MARKER, 1),
+        ]
+        .concat();
+
+        assert_eq!(stdout(&io, &code), "one\ntwo");
+    }
+
+    #[test]
+    fn files_are_read_and_written() {
+        let io = MemoryIo::new().with_file("/in.txt", "text");
+        let code = [
+            push(str("/in.txt")),
+            vec![opcodes::OPEN, opcodes::OPEN_READ],
+            store(100),
+            load(100),
+            vec![opcodes::EOF, opcodes::PRINT],
+            load(100),
+            vec![opcodes::READ_ALL, opcodes::PRINT],
+            load(100),
+            vec![opcodes::EOF, opcodes::PRINT],
+            push(str("/out.txt")),
+            vec![opcodes::OPEN, opcodes::OPEN_CREATE],
+            store(101),
+            load(101),
+            push(str("copied")),
+            vec![opcodes::WRITE],
+            load(101),
+            vec![opcodes::CLOSE],
+        ]
+        .concat();
+
+        assert_eq!(stdout(&io, &code), "false\ntext\ntrue\n");
+        assert_eq!(io.file("/out.txt"), Some(b"copied".to_vec()));
+    }
+
+    #[test]
+    fn exit_still_passes_on_buffered_output() {
+        let io = MemoryIo::new();
+        let mut vm = vm(&io);
+        let code = [
+            push(str("bye")),
+            vec![opcodes::PRINT],
+            push(num(3.)),
+            vec![opcodes::EXIT, opcodes::PRINT],
+        ]
+        .concat();
+
+        vm.set_stdout_buffering(StdoutBuffering::Full(1024)).unwrap();
+        vm.run(&code).unwrap();
+
+        assert_eq!(vm.exit_status(), Some(3));
+        assert_eq!(io.stdout(), b"");
+
+        drop(vm);
+
+        assert_eq!(io.stdout(), b"bye\n");
+        assert_eq!(run(&io, &[push(num(4.)), vec![opcodes::EXIT]].concat()).ok(), Some(Some(4)));
+    }
+
+    #[test]
+    fn rename_behaves_like_the_filesystem() {
+        let mut io = MemoryIo::new()
+            .with_file("/a.txt", "a")
+            .with_file("/b.txt", "b")
+            .with_file("/full/x.txt", "x");
+        let kind = |result: io::Result<()>| result.map_err(|e| e.kind());
+
+        assert_eq!(kind(io.rename(Path::new("/a.txt"), Path::new("/b.txt"))), Ok(()));
+        assert_eq!(io.file("/a.txt"), None);
+        assert_eq!(io.file("/b.txt"), Some(b"a".to_vec()));
+
+        assert_eq!(
+            kind(io.rename(Path::new("/b.txt"), Path::new("/full"))),
+            Err(ErrorKind::IsADirectory)
+        );
+        assert_eq!(
+            kind(io.rename(Path::new("/full"), Path::new("/b.txt"))),
+            Err(ErrorKind::NotADirectory)
+        );
+        assert_eq!(
+            kind(io.rename(Path::new("/full"), Path::new("/full/in"))),
+            Err(ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            kind(io.rename(Path::new("/missing"), Path::new("/c.txt"))),
+            Err(ErrorKind::NotFound)
+        );
+
+        io.create_dir(Path::new("/empty")).unwrap();
+        io.create_dir(Path::new("/full/sub")).unwrap();
+        assert_eq!(kind(io.create_dir(Path::new("/full/sub"))), Err(ErrorKind::AlreadyExists));
+        assert_eq!(kind(io.create_dir(Path::new("/missing/sub"))), Err(ErrorKind::NotFound));
+        assert_eq!(
+            kind(io.create_dir(Path::new("/full/x.txt/sub"))),
+            Err(ErrorKind::NotADirectory)
+        );
+
+        assert_eq!(
+            kind(io.rename(Path::new("/empty"), Path::new("/full"))),
+            Err(ErrorKind::DirectoryNotEmpty)
+        );
+        assert_eq!(kind(io.rename(Path::new("/full"), Path::new("/empty"))), Ok(()));
+        assert_eq!(io.file("/empty/x.txt"), Some(b"x".to_vec()));
+        assert_eq!(io.list_dir(Path::new("/empty")).unwrap(), ["sub", "x.txt"]);
+        assert_eq!(io.metadata(Path::new("/full")).unwrap(), None);
+    }
+}
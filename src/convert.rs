@@ -0,0 +1,436 @@
+use crate::errors::{ConvertError, VmError};
+use crate::heap::Heap;
+use crate::ordered_map::OrderedMap;
+use crate::values::{SpecificValue, TeaArray, TeaBool, TeaBytes, TeaNull, TeaNumber, TeaObject, TeaStr, Value, ValueKind};
+
+/// Converts a Rust value into a value scripts can use. Objects and arrays are allocated on the
+/// heap, so they must be stored somewhere bytecode can reach before the next collection.
+///
+/// `#[derive(IntoTea)]` implements this for structs, which become objects, and enums, which
+/// become objects with a `type` entry naming the variant.
+pub trait IntoTea {
+    fn into_tea(self, heap: &mut Heap) -> Value;
+}
+
+/// Converts a value from a script into a Rust value, failing with [`VmError::Convert`] when it
+/// has the wrong kind or is missing entries.
+///
+/// `#[derive(FromTea)]` implements this for structs and enums, reading the layout written by
+/// `#[derive(IntoTea)]`.
+pub trait FromTea: Sized {
+    fn from_tea(heap: &Heap, value: &Value) -> Result<Self, VmError>;
+}
+
+fn kind_name(kind: ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Number => "a number",
+        ValueKind::Str => "a string",
+        ValueKind::Bool => "a bool",
+        ValueKind::Function => "a function",
+        ValueKind::Object => "an object",
+        ValueKind::Array => "an array",
+        ValueKind::Null => "null",
+        ValueKind::Native => "a native function",
+        ValueKind::Bytes => "bytes",
+    }
+}
+
+fn mismatch(root: &str, expected: &str, value: &Value) -> VmError {
+    ConvertError(root.to_string(), format!("expected {expected}, found {}", kind_name(value.kind))).into()
+}
+
+/// Puts `prefix` in front of where a nested conversion failed, dropping the type name the
+/// nested path starts with, so a failure at `Inner.y` inside `Outer.inner` reads
+/// `Outer.inner.y`.
+fn nest(e: VmError, prefix: &str) -> VmError {
+    match e {
+        VmError::Convert(path, message) => {
+            let rest = path.find(['.', '[']).map_or("", |idx| &path[idx..]);
+
+            VmError::Convert(format!("{prefix}{rest}"), message)
+        }
+
+        e => e,
+    }
+}
+
+impl IntoTea for Value {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        self
+    }
+}
+
+impl FromTea for Value {
+    fn from_tea(_heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoTea for bool {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        TeaBool(self).to_value()
+    }
+}
+
+impl FromTea for bool {
+    fn from_tea(_heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        value
+            .as_tea_bool()
+            .map(|bool| bool.value())
+            .map_err(|_| mismatch("bool", "a bool", value))
+    }
+}
+
+impl IntoTea for String {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        TeaStr(self).to_value()
+    }
+}
+
+impl IntoTea for &str {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        TeaStr(self.to_string()).to_value()
+    }
+}
+
+impl FromTea for String {
+    fn from_tea(_heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        value
+            .as_tea_string()
+            .map(|str| str.value())
+            .map_err(|_| mismatch("String", "a string", value))
+    }
+}
+
+impl IntoTea for TeaBytes {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        self.to_value()
+    }
+}
+
+impl FromTea for TeaBytes {
+    fn from_tea(_heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        value.as_tea_bytes().map_err(|_| mismatch("TeaBytes", "bytes", value))
+    }
+}
+
+impl IntoTea for f64 {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        TeaNumber(self).to_value()
+    }
+}
+
+impl FromTea for f64 {
+    fn from_tea(_heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        value
+            .as_tea_number()
+            .map(|number| number.value())
+            .map_err(|_| mismatch("f64", "a number", value))
+    }
+}
+
+impl IntoTea for f32 {
+    fn into_tea(self, _heap: &mut Heap) -> Value {
+        TeaNumber(self as f64).to_value()
+    }
+}
+
+impl FromTea for f32 {
+    fn from_tea(heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        f64::from_tea(heap, value).map(|number| number as f32).map_err(|e| nest(e, "f32"))
+    }
+}
+
+/// Integers are numbers with no fractional part that fit the type.
+macro_rules! integer {
+    ($($ty:ident),*) => {
+        $(
+            impl IntoTea for $ty {
+                fn into_tea(self, _heap: &mut Heap) -> Value {
+                    TeaNumber(self as f64).to_value()
+                }
+            }
+
+            impl FromTea for $ty {
+                fn from_tea(heap: &Heap, value: &Value) -> Result<Self, VmError> {
+                    let number = f64::from_tea(heap, value).map_err(|e| nest(e, stringify!($ty)))?;
+
+                    let integer = value.as_tea_integer().map_err(|_| {
+                        ConvertError(stringify!($ty).to_string(), format!("expected a whole number, found {number}"))
+                    })?;
+
+                    $ty::try_from(integer).map_err(|_| {
+                        ConvertError(stringify!($ty).to_string(), format!("{integer} is out of range")).into()
+                    })
+                }
+            }
+        )*
+    };
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// `None` is `null`.
+impl<T: IntoTea> IntoTea for Option<T> {
+    fn into_tea(self, heap: &mut Heap) -> Value {
+        match self {
+            Some(value) => value.into_tea(heap),
+            None => TeaNull.to_value(),
+        }
+    }
+}
+
+impl<T: FromTea> FromTea for Option<T> {
+    fn from_tea(heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        match value.kind {
+            ValueKind::Null => Ok(None),
+            _ => T::from_tea(heap, value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoTea> IntoTea for Vec<T> {
+    fn into_tea(self, heap: &mut Heap) -> Value {
+        let items = self.into_iter().map(|item| item.into_tea(heap)).collect();
+
+        heap.alloc_array(TeaArray::new(items))
+    }
+}
+
+impl<T: FromTea> FromTea for Vec<T> {
+    fn from_tea(heap: &Heap, value: &Value) -> Result<Self, VmError> {
+        if value.kind != ValueKind::Array {
+            return Err(mismatch("Vec", "an array", value));
+        }
+
+        heap.array(value)?
+            .items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| T::from_tea(heap, item).map_err(|e| nest(e, &format!("Vec[{idx}]"))))
+            .collect()
+    }
+}
+
+/// Builds the objects that derived [`IntoTea`] implementations return.
+#[doc(hidden)]
+pub fn object(heap: &mut Heap, entries: OrderedMap<(Value, u8)>) -> Value {
+    heap.alloc_object(TeaObject::new(entries))
+}
+
+/// The entries of `value`, which derived [`FromTea`] implementations for `ty` read fields from.
+#[doc(hidden)]
+pub fn entries<'a>(heap: &'a Heap, value: &Value, ty: &str) -> Result<&'a OrderedMap<(Value, u8)>, VmError> {
+    if value.kind != ValueKind::Object {
+        return Err(mismatch(ty, "an object", value));
+    }
+
+    Ok(&heap.object(value)?.entries)
+}
+
+/// Converts the entry `key` of an object holding a `ty`.
+#[doc(hidden)]
+pub fn field<T: FromTea>(heap: &Heap, entries: &OrderedMap<(Value, u8)>, ty: &str, key: &str) -> Result<T, VmError> {
+    let (value, _) = entries
+        .get(key)
+        .ok_or_else(|| ConvertError(format!("{ty}.{key}"), "missing entry".to_string()))?;
+
+    T::from_tea(heap, value).map_err(|e| nest(e, &format!("{ty}.{key}")))
+}
+
+/// Like [`field`], but a missing entry becomes `T::default()`.
+#[doc(hidden)]
+pub fn field_or_default<T: FromTea + Default>(
+    heap: &Heap,
+    entries: &OrderedMap<(Value, u8)>,
+    ty: &str,
+    key: &str,
+) -> Result<T, VmError> {
+    match entries.get(key) {
+        Some(_) => field(heap, entries, ty, key),
+        None => Ok(T::default()),
+    }
+}
+
+#[doc(hidden)]
+pub fn unknown_variant(ty: &str, tag: &str, variant: &str) -> VmError {
+    ConvertError(format!("{ty}.{tag}"), format!("unknown variant '{variant}'")).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::RuntimeError;
+    use crate::heap::GcConfig;
+    use crate::values::READONLY;
+    use crate::{FromTea, IntoTea};
+
+    #[derive(Debug, PartialEq, Default, IntoTea, FromTea)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(Debug, PartialEq, IntoTea, FromTea)]
+    struct Shape {
+        #[tea(rename = "label")]
+        name: String,
+        #[tea(readonly)]
+        origin: Point,
+        corners: Vec<Point>,
+        #[tea(default)]
+        sides: u8,
+        #[tea(skip)]
+        cached: Option<f64>,
+    }
+
+    #[derive(Debug, PartialEq, IntoTea, FromTea)]
+    struct Pair(i32, bool);
+
+    #[derive(Debug, PartialEq, IntoTea, FromTea)]
+    enum Event {
+        Click { at: Point },
+        #[tea(rename = "key")]
+        KeyPress(String),
+        Close,
+    }
+
+    #[derive(Debug, PartialEq, IntoTea, FromTea)]
+    #[tea(tag = "kind")]
+    enum Tagged {
+        One { n: u8 },
+    }
+
+    fn heap() -> Heap {
+        Heap::with_config(GcConfig::default())
+    }
+
+    fn round_trip<T: IntoTea + FromTea>(heap: &mut Heap, value: T) -> T {
+        let value = value.into_tea(heap);
+
+        T::from_tea(heap, &value).unwrap()
+    }
+
+    fn error<T: FromTea>(heap: &Heap, value: &Value) -> String {
+        T::from_tea(heap, value).map(|_| ()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn structs_round_trip() {
+        let mut heap = heap();
+        let shape = Shape {
+            name: "square".to_string(),
+            origin: Point { x: 1., y: 2. },
+            corners: vec![Point { x: 0., y: 0. }, Point { x: 1., y: 1. }],
+            sides: 4,
+            cached: None,
+        };
+
+        assert_eq!(round_trip(&mut heap, shape), Shape {
+            name: "square".to_string(),
+            origin: Point { x: 1., y: 2. },
+            corners: vec![Point { x: 0., y: 0. }, Point { x: 1., y: 1. }],
+            sides: 4,
+            cached: None,
+        });
+        assert_eq!(round_trip(&mut heap, Pair(-3, true)), Pair(-3, true));
+    }
+
+    #[test]
+    fn field_attributes_shape_the_object() {
+        let mut heap = heap();
+        let shape = Shape {
+            name: "line".to_string(),
+            origin: Point::default(),
+            corners: Vec::new(),
+            sides: 0,
+            cached: Some(1.),
+        }
+        .into_tea(&mut heap);
+        let entries = &heap.object(&shape).unwrap().entries;
+
+        assert!(entries.get("label").is_some());
+        assert!(entries.get("name").is_none());
+        assert!(entries.get("cached").is_none());
+        assert_eq!(entries.get("origin").unwrap().1, READONLY);
+        assert_eq!(entries.get("corners").unwrap().1, 0);
+
+        let pair = Pair(1, false).into_tea(&mut heap);
+        let keys: Vec<_> = heap.object(&pair).unwrap().entries.iter().map(|(key, _)| key.clone()).collect();
+
+        assert_eq!(keys, ["0", "1"]);
+    }
+
+    #[test]
+    fn missing_default_and_skipped_fields_are_filled_in() {
+        let mut heap = heap();
+        let mut entries = OrderedMap::new();
+        entries.insert("label".to_string(), ("dot".into_tea(&mut heap), 0));
+        let origin = Point::default().into_tea(&mut heap);
+        entries.insert("origin".to_string(), (origin, 0));
+        let corners = Vec::<Point>::new().into_tea(&mut heap);
+        entries.insert("corners".to_string(), (corners, 0));
+        let value = object(&mut heap, entries);
+
+        let shape = Shape::from_tea(&heap, &value).unwrap();
+
+        assert_eq!(shape.sides, 0);
+        assert_eq!(shape.cached, None);
+    }
+
+    #[test]
+    fn enums_round_trip() {
+        let mut heap = heap();
+
+        assert_eq!(round_trip(&mut heap, Event::Click { at: Point { x: 3., y: 4. } }), Event::Click {
+            at: Point { x: 3., y: 4. }
+        });
+        assert_eq!(round_trip(&mut heap, Event::KeyPress("q".to_string())), Event::KeyPress("q".to_string()));
+        assert_eq!(round_trip(&mut heap, Event::Close), Event::Close);
+        assert_eq!(round_trip(&mut heap, Tagged::One { n: 7 }), Tagged::One { n: 7 });
+    }
+
+    #[test]
+    fn variants_are_named_by_their_tag() {
+        let mut heap = heap();
+
+        let key = Event::KeyPress("q".to_string()).into_tea(&mut heap);
+        let (variant, _) = heap.object(&key).unwrap().entries.get("type").unwrap();
+        assert_eq!(String::from_tea(&heap, variant).unwrap(), "key");
+
+        let one = Tagged::One { n: 1 }.into_tea(&mut heap);
+        let entries = &heap.object(&one).unwrap().entries;
+        assert!(entries.get("type").is_none());
+        assert_eq!(String::from_tea(&heap, &entries.get("kind").unwrap().0).unwrap(), "One");
+    }
+
+    #[test]
+    fn errors_name_where_the_conversion_failed() {
+        let mut heap = heap();
+
+        let mut entries = OrderedMap::new();
+        entries.insert("x".to_string(), (1.0.into_tea(&mut heap), 0));
+        let half = object(&mut heap, entries);
+        assert_eq!(error::<Point>(&heap, &half), "Cannot convert 'Point.y': missing entry");
+
+        let number = 1.0.into_tea(&mut heap);
+        assert_eq!(error::<Point>(&heap, &number), "Cannot convert 'Point': expected an object, found a number");
+
+        let mut entries = OrderedMap::new();
+        entries.insert("type".to_string(), ("Click".into_tea(&mut heap), 0));
+        entries.insert("at".to_string(), (half, 0));
+        let click = object(&mut heap, entries);
+        assert_eq!(error::<Event>(&heap, &click), "Cannot convert 'Event::Click.at.y': missing entry");
+
+        let mut entries = OrderedMap::new();
+        entries.insert("type".to_string(), ("Scroll".into_tea(&mut heap), 0));
+        let scroll = object(&mut heap, entries);
+        assert_eq!(error::<Event>(&heap, &scroll), "Cannot convert 'Event.type': unknown variant 'Scroll'");
+
+        let pairs = vec![Pair(1, true)].into_tea(&mut heap);
+        assert_eq!(error::<Vec<Point>>(&heap, &pairs), "Cannot convert 'Vec[0].x': missing entry");
+
+        let big = 300.into_tea(&mut heap);
+        assert_eq!(error::<u8>(&heap, &big), "Cannot convert 'u8': 300 is out of range");
+    }
+}
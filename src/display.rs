@@ -0,0 +1,196 @@
+use crate::heap::{Heap, HeapCell};
+use crate::values::{SpecificValue, Value, ValueKind};
+use std::fmt::{Display, Formatter, Result};
+
+/// Formats a value for people to read, following objects and arrays through the heap.
+///
+/// Strings are written as-is at the top level and quoted inside objects and arrays. Objects and
+/// arrays that contain themselves are written as `{...}` and `[...]` where they recur. Bytes are
+/// always written as `b"..."`, with anything other than printable ASCII escaped.
+pub struct DisplayValue<'a> {
+    heap: &'a Heap,
+    value: &'a Value,
+}
+
+impl<'a> DisplayValue<'a> {
+    pub fn new(heap: &'a Heap, value: &'a Value) -> Self {
+        DisplayValue { heap, value }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>, value: &Value, nested: bool, visiting: &mut Vec<u32>) -> Result {
+        match value.kind {
+            ValueKind::Number => match value.as_tea_number() {
+                Ok(number) => write_number(f, number.value()),
+                Err(_) => write!(f, "<invalid number>"),
+            },
+
+            ValueKind::Str => match value.as_tea_string() {
+                Ok(str) if nested => write!(f, "{:?}", str.value()),
+                Ok(str) => write!(f, "{}", str.value()),
+                Err(_) => write!(f, "<invalid string>"),
+            },
+
+            ValueKind::Bytes => write!(f, "b\"{}\"", value.data.escape_ascii()),
+
+            ValueKind::Bool => match value.as_tea_bool() {
+                Ok(bool) => write!(f, "{}", bool.value()),
+                Err(_) => write!(f, "<invalid bool>"),
+            },
+
+            ValueKind::Null => write!(f, "null"),
+
+            ValueKind::Native => match value.native_name() {
+                Some(name) => write!(f, "<native function {name}>"),
+                None => write!(f, "<invalid native function>"),
+            },
+
+            ValueKind::Function => match value.as_tea_function() {
+                Ok(function) => write!(f, "<function ({} bytes)>", function.code_len),
+                Err(_) => write!(f, "<invalid function>"),
+            },
+
+            ValueKind::Object | ValueKind::Array => {
+                let Some(handle) = value.handle() else {
+                    return write!(f, "<invalid reference>");
+                };
+
+                let Ok(cell) = self.heap.cell(handle) else {
+                    return write!(f, "<deallocated>");
+                };
+
+                if visiting.contains(&handle) {
+                    return match cell {
+                        HeapCell::Object(_) => write!(f, "{{...}}"),
+                        HeapCell::Array(_) => write!(f, "[...]"),
+                    };
+                }
+
+                visiting.push(handle);
+
+                match cell {
+                    HeapCell::Object(object) => {
+                        write!(f, "{{")?;
+
+                        for (idx, (key, (value, _))) in object.entries.iter().enumerate() {
+                            if idx > 0 {
+                                write!(f, ", ")?;
+                            }
+
+                            write!(f, "{key}: ")?;
+                            self.write(f, value, true, visiting)?;
+                        }
+
+                        write!(f, "}}")?;
+                    }
+
+                    HeapCell::Array(array) => {
+                        write!(f, "[")?;
+
+                        for (idx, item) in array.items.iter().enumerate() {
+                            if idx > 0 {
+                                write!(f, ", ")?;
+                            }
+
+                            self.write(f, item, true, visiting)?;
+                        }
+
+                        write!(f, "]")?;
+                    }
+                }
+
+                visiting.pop();
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Whole numbers are written without a fractional part, and very large or small magnitudes use
+/// exponent notation.
+fn write_number(f: &mut Formatter<'_>, number: f64) -> Result {
+    let magnitude = number.abs();
+
+    if number.is_nan() {
+        write!(f, "NaN")
+    } else if number.is_infinite() {
+        write!(f, "{}", if number > 0. { "inf" } else { "-inf" })
+    } else if magnitude != 0. && !(1e-6..1e16).contains(&magnitude) {
+        write!(f, "{number:e}")
+    } else if number.fract() == 0. {
+        write!(f, "{}", number as i64)
+    } else {
+        write!(f, "{number}")
+    }
+}
+
+impl Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.write(f, self.value, false, &mut vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::MemoryIo;
+    use crate::opcodes;
+    use crate::test_util::{array, load, num, object, push, store, str, stdout, string};
+
+    fn shown(value: Vec<u8>) -> Option<String> {
+        string(&[push(value), vec![opcodes::TOSTR]].concat()).ok()
+    }
+
+    #[test]
+    fn numbers_are_written_as_people_write_them() {
+        let cases = [
+            (3., "3"),
+            (-0.5, "-0.5"),
+            (1e20, "1e20"),
+            (1.5e-7, "1.5e-7"),
+            (f64::NAN, "NaN"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+
+        for (n, text) in cases {
+            assert_eq!(shown(num(n)).as_deref(), Some(text));
+        }
+    }
+
+    #[test]
+    fn strings_are_quoted_only_inside_objects_and_arrays() {
+        let nested = object(&[("name", str("tea")), ("sizes", array(&[num(1.), str("\"big\"")]))]);
+
+        assert_eq!(shown(str("tea")).as_deref(), Some("tea"));
+        assert_eq!(shown(nested).as_deref(), Some(r#"{name: "tea", sizes: [1, "\"big\""]}"#));
+        assert_eq!(shown(array(&[])).as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn values_that_contain_themselves_are_cut_short() {
+        let code = [
+            push(array(&[num(0.)])),
+            store(200),
+            load(200),
+            push(num(0.)),
+            load(200),
+            vec![opcodes::SET_DYN],
+            load(200),
+            vec![opcodes::TOSTR],
+        ];
+
+        assert_eq!(string(&code.concat()).ok().as_deref(), Some("[[...]]"));
+    }
+
+    #[test]
+    fn print_writes_a_line() {
+        let io = MemoryIo::new();
+        let code = [
+            push(object(&[("a", num(1.))])),
+            vec![opcodes::PRINT],
+            push(str("b")),
+            vec![opcodes::PRINT],
+        ];
+
+        assert_eq!(stdout(&io, &code.concat()), "{a: 1}\nb\n");
+    }
+}
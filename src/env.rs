@@ -0,0 +1,66 @@
+use crate::errors::VmError;
+use crate::scope::ENV_VAR;
+use crate::values::{SpecificValue, TeaNull, TeaStr};
+use crate::vm::Vm;
+
+/// Fills in the `env` object of the global scope. `get` pushes the value of the environment
+/// variable with the given name, or null if it is not set. Reading a variable needs the `env`
+/// permission.
+pub(crate) fn register(vm: &mut Vm) -> Result<(), VmError> {
+    vm.register_native_in(ENV_VAR, "get", 1, |vm, args| {
+        let name = args[0].as_tea_string()?.value();
+
+        Ok(match vm.files.env(&name)? {
+            Some(value) => TeaStr(value).to_value(),
+            None => TeaNull.to_value(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryIo;
+    use crate::opcodes;
+    use crate::permissions::Permissions;
+    use crate::scope::ARGS_VAR;
+    use crate::test_util::{get, load, push, store, str, stdout, vm};
+
+    /// Pushes the value of the environment variable `name`.
+    fn env(name: &str) -> Vec<u8> {
+        [push(str(name)), load(ENV_VAR), get("get"), vec![opcodes::CALL]].concat()
+    }
+
+    #[test]
+    fn args_are_an_array_of_strings() {
+        let mut vm = vm(&MemoryIo::new());
+
+        vm.set_args(&["a".to_string(), "b c".to_string()]);
+        vm.run(&[load(ARGS_VAR), vec![opcodes::TOSTR], store(200)].concat()).unwrap();
+
+        let args = vm.get_global(200).unwrap();
+        let args = args.as_tea_string().map(|str| str.value()).ok();
+
+        assert_eq!(args.as_deref(), Some(r#"["a", "b c"]"#));
+    }
+
+    #[test]
+    fn variables_are_read_through_the_backend() {
+        let io = MemoryIo::new().with_env("TEA", "green");
+        let code = [env("TEA"), vec![opcodes::PRINT], env("MISSING"), vec![opcodes::PRINT]];
+
+        assert_eq!(stdout(&io, &code.concat()), "green\nnull\n");
+    }
+
+    #[test]
+    fn variables_need_permission() {
+        let mut vm = vm(&MemoryIo::new().with_env("TEA", "green"));
+
+        vm.set_permissions(Permissions {
+            env: false,
+            ..Permissions::all()
+        });
+
+        assert!(vm.run(&env("TEA")).is_err());
+    }
+}
@@ -1,16 +1,22 @@
 use std::path::PathBuf;
 use crate::values::Value;
 
-pub(crate) trait RuntimeError {
+pub trait RuntimeError {
     fn raise(&self) -> !;
     fn to_string(&self) -> String;
 }
 
-pub(crate) struct DeallocatedError(pub String);
-pub(crate) struct NoValueError(pub String);
-pub(crate) struct CannotConstruct<'a>(pub String, pub &'a Value);
-pub(crate) struct NotAllowed(pub String);
-pub(crate) struct FileError(pub Option<PathBuf>, pub String);
+pub struct DeallocatedError(pub String);
+pub struct NoValueError(pub String);
+pub struct CannotConstruct<'a>(pub String, pub &'a Value);
+pub struct NotAllowed(pub String);
+pub struct FileError(pub Option<PathBuf>, pub String);
+pub struct StackUnderflow(pub String, pub usize);
+pub struct FormatError(pub String);
+/// Bytes that could not be decoded as text, and why.
+pub struct EncodingError(pub String);
+/// Where in the value the conversion failed, such as `Point.x`, and why.
+pub struct ConvertError(pub String, pub String);
 
 impl RuntimeError for DeallocatedError {
     fn raise(&self) -> ! {
@@ -63,3 +69,141 @@ impl RuntimeError for FileError {
         format!("File error @ {:?}: {}", self.0, self.1)
     }
 }
+
+impl RuntimeError for StackUnderflow {
+    fn raise(&self) -> ! {
+        panic!("{}", self.to_string())
+    }
+
+    fn to_string(&self) -> String {
+        format!("Stack underflow: '{}' needs {} value(s) on the stack.", self.0, self.1)
+    }
+}
+
+impl RuntimeError for FormatError {
+    fn raise(&self) -> ! {
+        panic!("{}", self.to_string())
+    }
+
+    fn to_string(&self) -> String {
+        format!("Format error: {}", self.0)
+    }
+}
+
+impl RuntimeError for EncodingError {
+    fn raise(&self) -> ! {
+        panic!("{}", self.to_string())
+    }
+
+    fn to_string(&self) -> String {
+        format!("Encoding error: {}", self.0)
+    }
+}
+
+impl RuntimeError for ConvertError {
+    fn raise(&self) -> ! {
+        panic!("{}", self.to_string())
+    }
+
+    fn to_string(&self) -> String {
+        format!("Cannot convert '{}': {}", self.0, self.1)
+    }
+}
+
+/// An error from running bytecode, or returned by a native function.
+#[derive(Debug, Clone)]
+pub enum VmError {
+    Deallocated(String),
+    NoValue(String),
+    /// The name of the item, and a description of the value it could not be made from.
+    CannotConstruct(String, String),
+    NotAllowed(String),
+    File(Option<PathBuf>, String),
+    StackUnderflow(String, usize),
+    Format(String),
+    Encoding(String),
+    /// Where a conversion between a Rust type and a value failed, and why.
+    Convert(String, String),
+    /// An error raised by a native function for its own reasons.
+    Native(String),
+    /// Not an error: `EXIT` ran with this status, and is unwinding every call.
+    Exit(i32),
+}
+
+impl RuntimeError for VmError {
+    fn raise(&self) -> ! {
+        panic!("{}", self.to_string())
+    }
+
+    fn to_string(&self) -> String {
+        match self {
+            VmError::Deallocated(item) => DeallocatedError(item.clone()).to_string(),
+            VmError::NoValue(item) => NoValueError(item.clone()).to_string(),
+            VmError::CannotConstruct(item, value) => {
+                format!("The item '{item}' could not be constructed from the bytes {value}")
+            }
+            VmError::NotAllowed(action) => NotAllowed(action.clone()).to_string(),
+            VmError::File(path, message) => FileError(path.clone(), message.clone()).to_string(),
+            VmError::StackUnderflow(op, count) => StackUnderflow(op.clone(), *count).to_string(),
+            VmError::Format(message) => FormatError(message.clone()).to_string(),
+            VmError::Encoding(message) => EncodingError(message.clone()).to_string(),
+            VmError::Convert(path, message) => ConvertError(path.clone(), message.clone()).to_string(),
+            VmError::Native(message) => format!("Native error: {message}"),
+            VmError::Exit(status) => format!("Exited with status {status}"),
+        }
+    }
+}
+
+impl From<DeallocatedError> for VmError {
+    fn from(e: DeallocatedError) -> Self {
+        VmError::Deallocated(e.0)
+    }
+}
+
+impl From<NoValueError> for VmError {
+    fn from(e: NoValueError) -> Self {
+        VmError::NoValue(e.0)
+    }
+}
+
+impl From<CannotConstruct<'_>> for VmError {
+    fn from(e: CannotConstruct<'_>) -> Self {
+        VmError::CannotConstruct(e.0, format!("{:?}", e.1))
+    }
+}
+
+impl From<NotAllowed> for VmError {
+    fn from(e: NotAllowed) -> Self {
+        VmError::NotAllowed(e.0)
+    }
+}
+
+impl From<FileError> for VmError {
+    fn from(e: FileError) -> Self {
+        VmError::File(e.0, e.1)
+    }
+}
+
+impl From<StackUnderflow> for VmError {
+    fn from(e: StackUnderflow) -> Self {
+        VmError::StackUnderflow(e.0, e.1)
+    }
+}
+
+impl From<FormatError> for VmError {
+    fn from(e: FormatError) -> Self {
+        VmError::Format(e.0)
+    }
+}
+
+impl From<EncodingError> for VmError {
+    fn from(e: EncodingError) -> Self {
+        VmError::Encoding(e.0)
+    }
+}
+
+impl From<ConvertError> for VmError {
+    fn from(e: ConvertError) -> Self {
+        VmError::Convert(e.0, e.1)
+    }
+}
@@ -0,0 +1,605 @@
+use crate::backend::{FileHandle, FileInfo, IoBackend, OpenMode, RealIo};
+use crate::errors::{FileError, NotAllowed, VmError};
+use crate::opcodes;
+use crate::fs;
+use crate::permissions::{self, Access, Permissions};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{absolute, Path, PathBuf};
+
+/// The first descriptor handed out by OPEN. Lower descriptors are the standard streams.
+const FIRST_FD: u32 = 3;
+
+struct OpenFile {
+    path: PathBuf,
+    file: BufReader<Box<dyn FileHandle>>,
+}
+
+/// When output written to stdout is passed on to the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StdoutBuffering {
+    /// Every write is passed on immediately.
+    Unbuffered,
+    /// Output is passed on at the end of each line.
+    #[default]
+    Line,
+    /// Output is passed on once this many bytes are waiting.
+    Full(usize),
+}
+
+/// The standard streams, and the files opened by bytecode, by descriptor. Both lead wherever
+/// the [`IoBackend`] does, once [`Permissions`] allow it. Each file stays open
+/// until CLOSE, and descriptors are never reused while the VM lives.
+///
+/// Output waiting in the stdout buffer is passed on before reading from stdin, so prompts are
+/// shown, and when the VM is dropped.
+pub(crate) struct Files {
+    open: HashMap<u32, OpenFile>,
+    next: u32,
+    buffering: StdoutBuffering,
+    stdout: Vec<u8>,
+    io: Box<dyn IoBackend>,
+    permissions: Permissions,
+    audit: Vec<Access>,
+}
+
+impl Files {
+    pub(crate) fn new() -> Self {
+        Files {
+            open: HashMap::new(),
+            next: FIRST_FD,
+            buffering: StdoutBuffering::default(),
+            stdout: Vec::new(),
+            io: Box::new(RealIo),
+            permissions: Permissions::default(),
+            audit: Vec::new(),
+        }
+    }
+
+    pub(crate) fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    pub(crate) fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    pub(crate) fn audit_log(&self) -> &[Access] {
+        &self.audit
+    }
+
+    /// Checks that `access` is allowed, and records it in the audit log. The standard streams
+    /// are only recorded the first time they are used.
+    pub(crate) fn allow(&mut self, access: Access) -> Result<(), NotAllowed> {
+        let allowed = match access {
+            Access::Read(_) | Access::Write(_) => {
+                let io = &mut self.io;
+
+                self.permissions.resolved(|path| resolve(io.as_mut(), path)).allows(&access)
+            }
+            _ => self.permissions.allows(&access),
+        };
+
+        if !allowed {
+            return Err(NotAllowed(access.to_string()));
+        }
+
+        let stream = matches!(access, Access::Stdin | Access::Stdout | Access::Stderr);
+
+        if !(stream && self.audit.contains(&access)) {
+            self.audit.push(access);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `path` through the backend and checks that `access` to it is allowed, returning
+    /// the path that was checked so that it is the one used.
+    fn check(&mut self, access: fn(PathBuf) -> Access, path: &Path) -> Result<PathBuf, NotAllowed> {
+        let path = resolve(self.io.as_mut(), path);
+
+        self.allow(access(path.clone()))?;
+
+        Ok(path)
+    }
+
+    /// Like [`Files::check`], for operations on a directory entry rather than what it leads to.
+    /// The last component of the path is not followed if it is a symlink.
+    fn check_entry(
+        &mut self,
+        access: fn(PathBuf) -> Access,
+        path: &Path,
+    ) -> Result<PathBuf, NotAllowed> {
+        let path = absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let path = resolve(self.io.as_mut(), parent).join(name);
+
+                self.allow(access(path.clone()))?;
+
+                Ok(path)
+            }
+            _ => self.check(access, &path),
+        }
+    }
+
+    /// Replaces the backend, first passing on buffered output to the old one. Files that are
+    /// already open stay with the backend that opened them.
+    pub(crate) fn set_io(&mut self, io: Box<dyn IoBackend>) -> Result<(), VmError> {
+        self.flush(0)?;
+        self.io = io;
+
+        Ok(())
+    }
+
+    /// Changes how stdout is buffered, first passing on anything already waiting.
+    pub(crate) fn set_buffering(&mut self, buffering: StdoutBuffering) -> Result<(), VmError> {
+        self.flush(0)?;
+        self.buffering = buffering;
+
+        Ok(())
+    }
+
+    /// Opens `path` in one of the `OPEN_*` modes and returns its descriptor.
+    pub(crate) fn open(&mut self, path: &Path, mode: u8) -> Result<u32, VmError> {
+        let mode = match mode {
+            opcodes::OPEN_READ => OpenMode::Read,
+            opcodes::OPEN_WRITE => OpenMode::Write,
+            opcodes::OPEN_APPEND => OpenMode::Append,
+            opcodes::OPEN_CREATE => OpenMode::Create,
+            mode => return Err(NotAllowed(format!("open mode {mode}")).into()),
+        };
+
+        let path = match mode {
+            OpenMode::Read => self.check(Access::Read, path)?,
+            _ => self.check(Access::Write, path)?,
+        };
+
+        let file = self
+            .io
+            .open(&path, mode)
+            .map_err(|e| FileError(Some(path.clone()), e.to_string()))?;
+
+        let fd = self.next;
+
+        self.next += 1;
+        self.open.insert(
+            fd,
+            OpenFile {
+                path,
+                file: BufReader::new(file),
+            },
+        );
+
+        Ok(fd)
+    }
+
+    pub(crate) fn close(&mut self, fd: u32) -> Result<(), FileError> {
+        self.open
+            .remove(&fd)
+            .map(|_| ())
+            .ok_or_else(|| FileError(None, format!("no file is open at fd {fd}")))
+    }
+
+    fn get(&mut self, fd: u32) -> Result<&mut OpenFile, FileError> {
+        self.open
+            .get_mut(&fd)
+            .ok_or_else(|| FileError(None, format!("no file is open at fd {fd}")))
+    }
+
+    pub(crate) fn write(&mut self, fd: u32, content: &[u8]) -> Result<(), VmError> {
+        match fd {
+            0 => {
+                self.allow(Access::Stdout)?;
+                self.write_stdout(content).map_err(|e| FileError(None, format!("stdout: {e}")).into())
+            }
+            1 => Err(NotAllowed("writing to stdin".to_string()).into()),
+            2 => {
+                self.allow(Access::Stderr)?;
+                self.io
+                    .write_stderr(content)
+                    .map_err(|e| FileError(None, format!("stderr: {e}")).into())
+            }
+            fd => {
+                let open = self.get(fd)?;
+
+                open.file
+                    .get_mut()
+                    .write_all(content)
+                    .map_err(|e| FileError(Some(open.path.clone()), e.to_string()).into())
+            }
+        }
+    }
+
+    fn write_stdout(&mut self, content: &[u8]) -> io::Result<()> {
+        self.stdout.extend_from_slice(content);
+
+        let ready = match self.buffering {
+            StdoutBuffering::Unbuffered => self.stdout.len(),
+            StdoutBuffering::Line => self.stdout.iter().rposition(|b| *b == b'\n').map_or(0, |idx| idx + 1),
+            StdoutBuffering::Full(size) if self.stdout.len() >= size => self.stdout.len(),
+            StdoutBuffering::Full(_) => 0,
+        };
+
+        if ready > 0 {
+            self.io.write_stdout(&self.stdout[..ready])?;
+            self.stdout.drain(..ready);
+        }
+
+        Ok(())
+    }
+
+    /// Passes on everything written to `fd` that is still buffered.
+    pub(crate) fn flush(&mut self, fd: u32) -> Result<(), VmError> {
+        match fd {
+            0 => {
+                if !self.stdout.is_empty() {
+                    self.io
+                        .write_stdout(&self.stdout)
+                        .map_err(|e| FileError(None, format!("stdout: {e}")))?;
+                    self.stdout.clear();
+                }
+
+                Ok(())
+            }
+            1 => Err(NotAllowed("flushing stdin".to_string()).into()),
+            2 => Ok(()),
+            fd => {
+                let open = self.get(fd)?;
+
+                open.file
+                    .get_mut()
+                    .flush()
+                    .map_err(|e| FileError(Some(open.path.clone()), e.to_string()).into())
+            }
+        }
+    }
+
+    /// The value of the environment variable `name`, if it is set.
+    pub(crate) fn env(&mut self, name: &str) -> Result<Option<String>, VmError> {
+        self.allow(Access::Env(name.to_string()))?;
+
+        self.io
+            .env(name)
+            .map_err(|e| FileError(None, format!("env: {e}")).into())
+    }
+
+    /// The names in the directory at `path`, sorted.
+    pub(crate) fn list_dir(&mut self, path: &Path) -> Result<Vec<String>, VmError> {
+        let path = self.check(Access::Read, path)?;
+
+        self.io.list_dir(&path).map_err(|e| file_error(&path, e))
+    }
+
+    pub(crate) fn metadata(&mut self, path: &Path) -> Result<Option<FileInfo>, VmError> {
+        let path = self.check(Access::Read, path)?;
+
+        self.io.metadata(&path).map_err(|e| file_error(&path, e))
+    }
+
+    pub(crate) fn create_dir(&mut self, path: &Path) -> Result<(), VmError> {
+        let path = self.check_entry(Access::Write, path)?;
+
+        self.io.create_dir(&path).map_err(|e| file_error(&path, e))
+    }
+
+    pub(crate) fn remove_dir(&mut self, path: &Path) -> Result<(), VmError> {
+        let path = self.check_entry(Access::Write, path)?;
+
+        self.io.remove_dir(&path).map_err(|e| file_error(&path, e))
+    }
+
+    /// Moving needs permission to write both where the file is and where it goes.
+    pub(crate) fn rename(&mut self, from: &Path, to: &Path) -> Result<(), VmError> {
+        let from = self.check_entry(Access::Write, from)?;
+        let to = self.check_entry(Access::Write, to)?;
+
+        self.io.rename(&from, &to).map_err(|e| file_error(&from, e))
+    }
+
+    /// Runs `f` on the reader for `fd`, which is stdin or a file from OPEN.
+    fn read<T>(&mut self, fd: u32, f: impl FnOnce(&mut dyn BufRead) -> io::Result<T>) -> Result<T, VmError> {
+        match fd {
+            0 => Err(NotAllowed("reading from stdout".to_string()).into()),
+            2 => Err(NotAllowed("reading from stderr".to_string()).into()),
+            1 => {
+                self.allow(Access::Stdin)?;
+                self.flush(0)?;
+
+                self.io
+                    .stdin()
+                    .and_then(|mut stdin| f(&mut *stdin))
+                    .map_err(|e| FileError(None, format!("stdin: {e}")).into())
+            }
+            fd => {
+                let open = self.get(fd)?;
+
+                f(&mut open.file).map_err(|e| FileError(Some(open.path.clone()), e.to_string()).into())
+            }
+        }
+    }
+
+    /// The next line, including its line ending, or `None` at the end of the file.
+    pub(crate) fn read_line(&mut self, fd: u32) -> Result<Option<String>, VmError> {
+        self.read(fd, |reader| {
+            let mut line = String::new();
+
+            Ok(match reader.read_line(&mut line)? {
+                0 => None,
+                _ => Some(line),
+            })
+        })
+    }
+
+    /// Everything up to the end of the file, which is empty at the end of the file.
+    pub(crate) fn read_all(&mut self, fd: u32) -> Result<String, VmError> {
+        self.read(fd, |reader| {
+            let mut str = String::new();
+
+            reader.read_to_string(&mut str)?;
+
+            Ok(str)
+        })
+    }
+
+    /// Everything up to the end of the file as bytes, which need not be valid UTF-8.
+    pub(crate) fn read_all_bytes(&mut self, fd: u32) -> Result<Vec<u8>, VmError> {
+        self.read(fd, |reader| {
+            let mut bytes = Vec::new();
+
+            reader.read_to_end(&mut bytes)?;
+
+            Ok(bytes)
+        })
+    }
+
+    /// Up to `count` characters, or `None` at the end of the file. Only whole characters are
+    /// read, so one is never split between two reads. A character that is not valid UTF-8 is an
+    /// error, and its bytes are lost.
+    pub(crate) fn read_chars(&mut self, fd: u32, count: usize) -> Result<Option<String>, VmError> {
+        self.read(fd, |reader| {
+            let mut str = String::new();
+
+            for _ in 0..count {
+                let mut char = [0u8; 4];
+
+                if reader.read(&mut char[..1])? == 0 {
+                    break;
+                }
+
+                let len = match char[0] {
+                    0xC0..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF7 => 4,
+                    _ => 1,
+                };
+
+                reader.read_exact(&mut char[1..len])?;
+
+                let char = std::str::from_utf8(&char[..len])
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))?;
+
+                str.push_str(char);
+            }
+
+            Ok((count == 0 || !str.is_empty()).then_some(str))
+        })
+    }
+
+    /// Up to `count` bytes, or `None` at the end of the file.
+    pub(crate) fn read_bytes(&mut self, fd: u32, count: usize) -> Result<Option<Vec<u8>>, VmError> {
+        self.read(fd, |reader| {
+            let mut bytes = Vec::new();
+
+            reader.take(count as u64).read_to_end(&mut bytes)?;
+
+            Ok((count == 0 || !bytes.is_empty()).then_some(bytes))
+        })
+    }
+
+    pub(crate) fn at_eof(&mut self, fd: u32) -> Result<bool, VmError> {
+        self.read(fd, |reader| Ok(reader.fill_buf()?.is_empty()))
+    }
+}
+
+/// Makes `path` absolute and resolves symlinks, `.` and `..` the way opening it through `io`
+/// would, so a symlink beneath a granted path cannot lead outside it. Only the longest part of the
+/// path that exists can be resolved by the backend. The rest is resolved without touching it, so
+/// paths that do not exist yet can be checked too.
+///
+/// The check happens before the path is used, so a symlink created in between is still
+/// followed.
+fn resolve(io: &mut dyn IoBackend, path: &Path) -> PathBuf {
+    let path = absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let components: Vec<_> = path.components().collect();
+
+    for existing in (1..=components.len()).rev() {
+        let prefix: PathBuf = components[..existing].iter().collect();
+
+        let Ok(resolved) = io.canonicalize(&prefix) else {
+            continue;
+        };
+
+        return fs::normalize(&resolved.join(components[existing..].iter().collect::<PathBuf>()));
+    }
+
+    permissions::normalize(&path)
+}
+
+fn file_error(path: &Path, e: io::Error) -> VmError {
+    FileError(Some(path.to_path_buf()), e.to_string()).into()
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let _ = self.flush(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FileKind, MemoryIo};
+    use crate::test_util::{num, push, run, str, vm};
+
+    /// Writes `s` to `fd`.
+    fn write(fd: u32, s: &str) -> Vec<u8> {
+        [push(num(fd as f64)), push(str(s)), vec![opcodes::WRITE]].concat()
+    }
+
+    /// Files that may do anything, with `contents` open for reading at the returned descriptor.
+    fn open(contents: &[u8]) -> (Files, u32) {
+        let mut files = Files::new();
+
+        files.set_io(Box::new(MemoryIo::new().with_file("/data", contents))).unwrap();
+        files.set_permissions(Permissions::all());
+
+        let fd = files.open(Path::new("/data"), opcodes::OPEN_READ).unwrap();
+
+        (files, fd)
+    }
+
+    #[test]
+    fn characters_are_not_split_between_reads() {
+        let (mut files, fd) = open("aé€😀".as_bytes());
+
+        assert_eq!(files.read_chars(fd, 1).ok(), Some(Some("a".to_string())));
+        assert_eq!(files.read_chars(fd, 2).ok(), Some(Some("é€".to_string())));
+        assert_eq!(files.read_chars(fd, 5).ok(), Some(Some("😀".to_string())));
+        assert_eq!(files.read_chars(fd, 1).ok(), Some(None));
+        assert_eq!(files.read_chars(fd, 0).ok(), Some(Some(String::new())));
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let (mut files, fd) = open(b"a\xffb");
+
+        assert_eq!(files.read_chars(fd, 1).ok(), Some(Some("a".to_string())));
+        assert!(files.read_chars(fd, 1).is_err());
+        assert_eq!(files.read_chars(fd, 1).ok(), Some(Some("b".to_string())));
+    }
+
+    #[test]
+    fn huge_counts_read_what_is_there() {
+        let (mut files, fd) = open(b"abc");
+
+        assert_eq!(files.read_bytes(fd, usize::MAX).ok(), Some(Some(b"abc".to_vec())));
+        assert_eq!(files.read_bytes(fd, usize::MAX).ok(), Some(None));
+
+        let (mut files, fd) = open(b"abc");
+
+        assert_eq!(files.read_chars(fd, usize::MAX).ok(), Some(Some("abc".to_string())));
+    }
+
+    #[test]
+    fn the_backend_is_given_the_path_that_was_checked() {
+        let io = MemoryIo::new().with_file("/data/a.txt", "a");
+        let mut files = Files::new();
+        let mut permissions = Permissions::none();
+
+        permissions.read.allow("/data");
+        permissions.write.allow("/data");
+        files.set_io(Box::new(io.clone())).unwrap();
+        files.set_permissions(permissions);
+
+        let fd = files.open(Path::new("/data/sub/../a.txt"), opcodes::OPEN_READ).unwrap();
+
+        assert_eq!(files.read_all(fd).ok(), Some("a".to_string()));
+        assert!(files.open(Path::new("/data/../a.txt"), opcodes::OPEN_READ).is_err());
+
+        files.create_dir(Path::new("/data/./new")).unwrap();
+        files.rename(Path::new("/data/a.txt"), Path::new("/data/new/../b.txt")).unwrap();
+
+        assert_eq!(io.file("/data/b.txt"), Some(b"a".to_vec()));
+        let info = files.metadata(Path::new("/data/new/.")).unwrap();
+
+        assert_eq!(info.map(|info| info.kind), Some(FileKind::Dir));
+        assert_eq!(
+            files.audit_log().last(),
+            Some(&Access::Read(PathBuf::from("/data/new")))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_lead_outside_a_grant() {
+        let root = std::env::temp_dir().join(format!("teavm-files-{}", std::process::id()));
+        let (granted, outside) = (root.join("granted"), root.join("outside"));
+
+        std::fs::create_dir_all(&granted).unwrap();
+        std::fs::create_dir_all(outside.join("inner")).unwrap();
+        std::os::unix::fs::symlink(outside.join("inner"), granted.join("link")).unwrap();
+
+        let mut permissions = Permissions::none();
+
+        permissions.read.allow(&granted);
+
+        let mut files = Files::new();
+
+        files.set_permissions(permissions.clone());
+
+        let mut allows = |path: &str| files.check(Access::Read, &granted.join(path)).is_ok();
+
+        assert!(allows("file.txt"));
+        assert!(allows("new/dir/file.txt"));
+        assert!(!allows("link"));
+        assert!(!allows("link/file.txt"));
+        assert!(!allows("link/../file.txt"));
+        assert!(files.check_entry(Access::Read, &granted.join("link")).is_ok());
+
+        // Other backends have their own filesystem, so symlinks on the host do not matter to them.
+        let mut files = Files::new();
+
+        files.set_io(Box::new(MemoryIo::new())).unwrap();
+        files.set_permissions(permissions);
+
+        assert!(files.check(Access::Read, &granted.join("link/file.txt")).is_ok());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn stderr_is_written_at_once_and_stdout_at_the_end_of_a_line() {
+        let io = MemoryIo::new();
+        let mut vm = vm(&io);
+
+        vm.run(&[write(0, "a"), write(2, "oops\n"), write(0, "b")].concat()).unwrap();
+
+        assert_eq!(io.stdout(), b"");
+        assert_eq!(io.stderr(), b"oops\n");
+
+        vm.run(&write(0, "\nc")).unwrap();
+
+        assert_eq!(io.stdout(), b"ab\n");
+
+        drop(vm);
+
+        assert_eq!(io.stdout(), b"ab\nc");
+    }
+
+    #[test]
+    fn the_standard_streams_only_go_one_way() {
+        let io = MemoryIo::new();
+        let read = |fd: f64| [push(num(fd)), vec![opcodes::READ_ALL]].concat();
+
+        assert!(run(&io, &write(1, "a")).is_err());
+        assert!(run(&io, &read(0.)).is_err());
+        assert!(run(&io, &read(2.)).is_err());
+        assert_eq!(io.stdout(), b"");
+    }
+
+    #[test]
+    fn stderr_needs_permission() {
+        let io = MemoryIo::new();
+        let mut vm = vm(&io);
+
+        vm.set_permissions(Permissions {
+            stderr: false,
+            ..Permissions::all()
+        });
+
+        assert!(vm.run(&write(2, "oops")).is_err());
+        assert_eq!(io.stderr(), b"");
+    }
+}
@@ -0,0 +1,298 @@
+use crate::display::DisplayValue;
+use crate::errors::FormatError;
+use crate::heap::Heap;
+use crate::values::{SpecificValue, Value, ValueKind};
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// A parsed `{index:[[fill]align][0][width][.precision][type]}` placeholder.
+struct Spec {
+    index: Option<usize>,
+    fill: char,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+/// Fills in the placeholders of `template` with `args`.
+///
+/// `{}` takes the next argument and `{N}` takes argument N. After a colon, a placeholder can set
+/// a fill character and alignment (`<`, `>` or `^`), `0` to pad numbers with zeroes, a minimum
+/// width, a precision (decimal places for numbers, maximum characters otherwise) and a type:
+/// `x`, `X`, `o` or `b` to write a whole number in another radix, or `e` for exponent notation.
+/// `{{` and `}}` are literal braces.
+pub(crate) fn format(heap: &Heap, template: &str, args: &[Value]) -> Result<String, FormatError> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next = 0usize;
+
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+
+            '{' => {
+                let spec = parse_spec(&mut chars)?;
+
+                let index = spec.index.unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                });
+
+                let arg = args.get(index).ok_or_else(|| {
+                    FormatError(format!("no argument for placeholder {index}"))
+                })?;
+
+                out.push_str(&format_arg(heap, arg, &spec)?);
+            }
+
+            '}' => return Err(FormatError("unmatched '}'".to_string())),
+
+            char => out.push(char),
+        }
+    }
+
+    Ok(out)
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+
+    while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        digits.push(*digit);
+        chars.next();
+    }
+
+    digits.parse().ok()
+}
+
+fn parse_align(char: Option<char>) -> Option<Align> {
+    match char? {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+fn parse_spec(chars: &mut Peekable<Chars>) -> Result<Spec, FormatError> {
+    let mut spec = Spec {
+        index: parse_number(chars),
+        fill: ' ',
+        align: None,
+        zero: false,
+        width: 0,
+        precision: None,
+        kind: None,
+    };
+
+    if chars.peek() == Some(&':') {
+        chars.next();
+
+        let mut lookahead = chars.clone();
+        let first = lookahead.next();
+
+        if let Some(align) = parse_align(lookahead.next()) {
+            spec.fill = first.unwrap_or(' ');
+            spec.align = Some(align);
+            chars.nth(1);
+        } else if let Some(align) = parse_align(first) {
+            spec.align = Some(align);
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'0') {
+            spec.zero = true;
+            chars.next();
+        }
+
+        spec.width = parse_number(chars).unwrap_or(0);
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(
+                parse_number(chars)
+                    .ok_or_else(|| FormatError("missing precision after '.'".to_string()))?,
+            );
+        }
+
+        if let Some(kind) = chars.peek().copied().filter(|c| *c != '}') {
+            if !matches!(kind, 'x' | 'X' | 'o' | 'b' | 'e') {
+                return Err(FormatError(format!("unknown format type '{kind}'")));
+            }
+
+            spec.kind = Some(kind);
+            chars.next();
+        }
+    }
+
+    match chars.next() {
+        Some('}') => Ok(spec),
+        Some(char) => Err(FormatError(format!("unexpected '{char}' in placeholder"))),
+        None => Err(FormatError("unclosed placeholder".to_string())),
+    }
+}
+
+fn format_arg(heap: &Heap, arg: &Value, spec: &Spec) -> Result<String, FormatError> {
+    let is_number = arg.kind == ValueKind::Number;
+
+    let body = if let Some(kind @ ('x' | 'X' | 'o' | 'b')) = spec.kind {
+        let value = arg.as_tea_integer().map_err(|_| {
+            FormatError(format!("'{kind}' needs a whole number"))
+        })?;
+
+        let digits = match kind {
+            'x' => format!("{:x}", value.unsigned_abs()),
+            'X' => format!("{:X}", value.unsigned_abs()),
+            'o' => format!("{:o}", value.unsigned_abs()),
+            _ => format!("{:b}", value.unsigned_abs()),
+        };
+
+        if value < 0 {
+            format!("-{digits}")
+        } else {
+            digits
+        }
+    } else if is_number && (spec.kind == Some('e') || spec.precision.is_some()) {
+        let value = arg.as_tea_number().map_err(|_| FormatError("invalid number".to_string()))?.value();
+
+        match (spec.kind, spec.precision) {
+            (Some(_), Some(precision)) => format!("{value:.precision$e}"),
+            (Some(_), None) => format!("{value:e}"),
+            (None, precision) => format!("{value:.*}", precision.unwrap_or(0)),
+        }
+    } else if spec.kind == Some('e') {
+        return Err(FormatError("'e' needs a number".to_string()));
+    } else {
+        let text = DisplayValue::new(heap, arg).to_string();
+
+        match spec.precision {
+            Some(precision) => text.chars().take(precision).collect(),
+            None => text,
+        }
+    };
+
+    let len = body.chars().count();
+
+    if len >= spec.width {
+        return Ok(body);
+    }
+
+    let padding = spec.width - len;
+
+    if spec.zero && is_number && spec.align.is_none() {
+        let (sign, digits) = body.split_at(if body.starts_with('-') { 1 } else { 0 });
+
+        return Ok(format!("{sign}{}{digits}", "0".repeat(padding)));
+    }
+
+    let align = spec
+        .align
+        .unwrap_or(if is_number { Align::Right } else { Align::Left });
+
+    let (before, after) = match align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+
+    let fill = |count: usize| spec.fill.to_string().repeat(count);
+
+    Ok(format!("{}{body}{}", fill(before), fill(after)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::GcConfig;
+    use crate::values::{TeaNumber, TeaStr};
+
+    fn fmt(template: &str, args: &[Value]) -> Result<String, String> {
+        format(&Heap::with_config(GcConfig::default()), template, args).map_err(|e| e.0)
+    }
+
+    fn n(n: f64) -> Value {
+        TeaNumber(n).to_value()
+    }
+
+    fn s(s: &str) -> Value {
+        TeaStr(s.to_string()).to_value()
+    }
+
+    #[test]
+    fn placeholders_take_arguments_in_order_or_by_index() {
+        assert_eq!(fmt("{} and {}", &[n(1.), s("two")]), Ok("1 and two".into()));
+        assert_eq!(fmt("{1}{0}{}", &[s("a"), s("b")]), Ok("baa".into()));
+        assert_eq!(fmt("{{}} {{{}}}", &[n(3.)]), Ok("{} {3}".into()));
+    }
+
+    #[test]
+    fn width_fill_and_alignment() {
+        assert_eq!(fmt("[{:5}]", &[s("ab")]), Ok("[ab   ]".into()));
+        assert_eq!(fmt("[{:5}]", &[n(42.)]), Ok("[   42]".into()));
+        assert_eq!(fmt("[{:>5}]", &[s("ab")]), Ok("[   ab]".into()));
+        assert_eq!(fmt("[{:<5}]", &[n(42.)]), Ok("[42   ]".into()));
+        assert_eq!(fmt("[{:^6}]", &[s("ab")]), Ok("[  ab  ]".into()));
+        assert_eq!(fmt("[{:*^7}]", &[s("ab")]), Ok("[**ab***]".into()));
+        assert_eq!(fmt("[{:<<4}]", &[s("ab")]), Ok("[ab<<]".into()));
+        assert_eq!(fmt("[{:2}]", &[s("long")]), Ok("[long]".into()));
+    }
+
+    #[test]
+    fn zero_padding_goes_after_the_sign() {
+        assert_eq!(fmt("{:05}", &[n(-42.)]), Ok("-0042".into()));
+        assert_eq!(fmt("{:05}", &[s("ab")]), Ok("ab   ".into()));
+        assert_eq!(fmt("{:08b}", &[n(5.)]), Ok("00000101".into()));
+    }
+
+    #[test]
+    fn precision_limits_decimals_or_characters() {
+        assert_eq!(fmt("{:.2}", &[n(1.23456)]), Ok("1.23".into()));
+        assert_eq!(fmt("{:.0}", &[n(2.5)]), Ok("2".into()));
+        assert_eq!(fmt("{:8.3}", &[n(1.)]), Ok("   1.000".into()));
+        assert_eq!(fmt("{:.3}", &[s("abcdef")]), Ok("abc".into()));
+    }
+
+    #[test]
+    fn radix_and_exponent_types() {
+        assert_eq!(fmt("{:x} {:X}", &[n(255.), n(255.)]), Ok("ff FF".into()));
+        assert_eq!(fmt("{:o}", &[n(8.)]), Ok("10".into()));
+        assert_eq!(fmt("{:b}", &[n(-5.)]), Ok("-101".into()));
+        assert_eq!(fmt("{:e}", &[n(1234.5)]), Ok("1.2345e3".into()));
+        assert_eq!(fmt("{:.2e}", &[n(1234.5)]), Ok("1.23e3".into()));
+    }
+
+    #[test]
+    fn malformed_templates_are_errors() {
+        assert_eq!(fmt("{", &[n(1.)]), Err("unclosed placeholder".into()));
+        assert_eq!(fmt("}", &[]), Err("unmatched '}'".into()));
+        assert_eq!(fmt("{:q}", &[n(1.)]), Err("unknown format type 'q'".into()));
+        assert_eq!(fmt("{:.}", &[n(1.)]), Err("missing precision after '.'".into()));
+        assert_eq!(fmt("{0a}", &[n(1.)]), Err("unexpected 'a' in placeholder".into()));
+        assert_eq!(fmt("{:xx}", &[n(1.)]), Err("unexpected 'x' in placeholder".into()));
+    }
+
+    #[test]
+    fn arguments_must_suit_their_placeholders() {
+        assert_eq!(fmt("{} {}", &[n(1.)]), Err("no argument for placeholder 1".into()));
+        assert_eq!(fmt("{2}", &[n(1.)]), Err("no argument for placeholder 2".into()));
+        assert_eq!(fmt("{:x}", &[n(1.5)]), Err("'x' needs a whole number".into()));
+        assert_eq!(fmt("{:e}", &[s("a")]), Err("'e' needs a number".into()));
+    }
+}
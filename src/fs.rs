@@ -0,0 +1,219 @@
+use crate::backend::FileKind;
+use crate::convert::{self, IntoTea};
+use crate::errors::VmError;
+use crate::ordered_map::OrderedMap;
+use crate::scope::FS_VAR;
+use crate::values::{SpecificValue, TeaBool, TeaNull, TeaNumber, TeaStr, Value};
+use crate::vm::Vm;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Fills in the `fs` object of the global scope.
+///
+/// - `list(path)` pushes the names in a directory, sorted.
+/// - `stat(path)` pushes an object with the `kind` (`"file"`, `"dir"` or `"other"`), `size`,
+///   `modified` time in seconds since the Unix epoch (or null) and `readonly` flag of a path, or
+///   null if nothing is there.
+/// - `exists(path)` pushes whether anything is at a path.
+/// - `mkdir(path)` creates a directory in one that exists, and `rmdir(path)` removes an empty
+///   directory.
+/// - `rename(from, to)` moves a file or directory.
+/// - `join(base, path)` and `normalize(path)` work on paths without touching the filesystem.
+///
+/// Functions that touch the filesystem go through the I/O backend and need permission to read
+/// or write the paths they are given.
+pub(crate) fn register(vm: &mut Vm) -> Result<(), VmError> {
+    vm.register_native_in(FS_VAR, "list", 1, |vm, args| {
+        let path = args[0].as_tea_string()?.value();
+        let names = vm.files.list_dir(Path::new(&path))?;
+
+        Ok(names.into_tea(&mut vm.heap))
+    })?;
+
+    vm.register_native_in(FS_VAR, "stat", 1, |vm, args| {
+        let path = args[0].as_tea_string()?.value();
+
+        let Some(info) = vm.files.metadata(Path::new(&path))? else {
+            return Ok(TeaNull.to_value());
+        };
+
+        let kind = match info.kind {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Other => "other",
+        };
+
+        let modified = info
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| TeaNumber(since.as_secs_f64()).to_value())
+            .unwrap_or_else(|| TeaNull.to_value());
+
+        let entries: OrderedMap<(Value, u8)> = OrderedMap::from([
+            ("kind".into(), (TeaStr(kind.to_string()).to_value(), 0u8)),
+            ("size".into(), (TeaNumber(info.size as f64).to_value(), 0u8)),
+            ("modified".into(), (modified, 0u8)),
+            ("readonly".into(), (TeaBool(info.readonly).to_value(), 0u8)),
+        ]);
+
+        Ok(convert::object(&mut vm.heap, entries))
+    })?;
+
+    vm.register_native_in(FS_VAR, "exists", 1, |vm, args| {
+        let path = args[0].as_tea_string()?.value();
+        let exists = vm.files.metadata(Path::new(&path))?.is_some();
+
+        Ok(TeaBool(exists).to_value())
+    })?;
+
+    vm.register_native_in(FS_VAR, "mkdir", 1, |vm, args| {
+        let path = args[0].as_tea_string()?.value();
+
+        vm.files.create_dir(Path::new(&path))?;
+
+        Ok(TeaNull.to_value())
+    })?;
+
+    vm.register_native_in(FS_VAR, "rmdir", 1, |vm, args| {
+        let path = args[0].as_tea_string()?.value();
+
+        vm.files.remove_dir(Path::new(&path))?;
+
+        Ok(TeaNull.to_value())
+    })?;
+
+    vm.register_native_in(FS_VAR, "rename", 2, |vm, args| {
+        let from = args[0].as_tea_string()?.value();
+        let to = args[1].as_tea_string()?.value();
+
+        vm.files.rename(Path::new(&from), Path::new(&to))?;
+
+        Ok(TeaNull.to_value())
+    })?;
+
+    vm.register_native_in(FS_VAR, "join", 2, |_vm, args| {
+        let base = args[0].as_tea_string()?.value();
+        let path = args[1].as_tea_string()?.value();
+
+        Ok(TeaStr(Path::new(&base).join(path).to_string_lossy().into_owned()).to_value())
+    })?;
+
+    vm.register_native_in(FS_VAR, "normalize", 1, |_vm, args| {
+        let path = args[0].as_tea_string()?.value();
+
+        Ok(TeaStr(normalize(Path::new(&path)).to_string_lossy().into_owned()).to_value())
+    })
+}
+
+/// Resolves `.` and `..` in `path` without touching the filesystem. A relative path keeps the
+/// `..` that climb above where it starts, and `..` at the root stays at the root. Permissions
+/// check paths with this too, once the part of them that exists has been resolved.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            component => out.push(component),
+        }
+    }
+
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryIo;
+    use crate::opcodes;
+    use crate::permissions::Permissions;
+    use crate::test_util::{get, load, push, run, str, stdout, vm};
+
+    /// Calls `fs.name` with `args`, which are strings, and prints its result.
+    fn print(name: &str, args: &[&str]) -> Vec<u8> {
+        let args: Vec<u8> = args.iter().flat_map(|arg| push(str(arg))).collect();
+
+        [args, load(FS_VAR), get(name), vec![opcodes::CALL, opcodes::PRINT]].concat()
+    }
+
+    fn io() -> MemoryIo {
+        MemoryIo::new()
+            .with_file("/data/b.txt", "hello")
+            .with_file("/data/a/c.txt", "")
+    }
+
+    #[test]
+    fn list_names_a_directory_in_order() {
+        assert_eq!(stdout(&io(), &print("list", &["/data"])), "[\"a\", \"b.txt\"]\n");
+        assert!(run(&io(), &print("list", &["/missing"])).is_err());
+    }
+
+    #[test]
+    fn stat_describes_what_is_at_a_path() {
+        let code = [
+            print("stat", &["/data/b.txt"]),
+            print("stat", &["/data/a"]),
+            print("stat", &["/data/missing"]),
+            print("exists", &["/data/a/c.txt"]),
+        ];
+
+        let printed = stdout(&io(), &code.concat());
+        let lines: Vec<&str> = printed.lines().collect();
+
+        assert_eq!(lines[0], r#"{kind: "file", size: 5, modified: null, readonly: false}"#);
+        assert!(lines[1].starts_with(r#"{kind: "dir", "#), "{}", lines[1]);
+        assert_eq!(lines[2..], ["null", "true"]);
+    }
+
+    #[test]
+    fn directories_are_made_moved_and_removed() {
+        let io = io();
+        let code = [
+            print("mkdir", &["/data/new"]),
+            print("rename", &["/data/b.txt", "/data/new/b.txt"]),
+            print("list", &["/data/new"]),
+            print("rmdir", &["/data/a"]),
+        ];
+
+        assert!(run(&io, &code.concat()).is_err());
+        assert_eq!(io.stdout(), b"null\nnull\n[\"b.txt\"]\n");
+        assert_eq!(io.file("/data/new/b.txt"), Some(b"hello".to_vec()));
+        assert!(run(&io, &print("mkdir", &["/missing/new"])).is_err());
+    }
+
+    #[test]
+    fn paths_are_joined_and_normalized_without_the_filesystem() {
+        let code = [
+            print("join", &["/data", "a/../b.txt"]),
+            print("normalize", &["/data/./a/../../b"]),
+        ];
+
+        assert_eq!(stdout(&MemoryIo::new(), &code.concat()), "/data/a/../b.txt\n/b\n");
+        assert_eq!(normalize(Path::new("../a/./..")), Path::new(".."));
+        assert_eq!(normalize(Path::new("a/..")), Path::new("."));
+    }
+
+    #[test]
+    fn the_filesystem_needs_permission() {
+        let mut vm = vm(&io());
+        let mut permissions = Permissions::default();
+
+        permissions.read.allow("/data/a");
+        vm.set_permissions(permissions);
+
+        assert!(vm.run(&print("list", &["/data/a"])).is_ok());
+        assert!(vm.run(&print("list", &["/data"])).is_err());
+        assert!(vm.run(&print("mkdir", &["/data/a/new"])).is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use crate::errors::{CannotConstruct, DeallocatedError, NotAllowed, RuntimeError, VmError};
+use crate::scope::Scope;
+use crate::values::{SpecificValue, TeaArray, TeaObject, Value, ValueKind};
+
+/// A value stored on the heap.
+#[derive(Debug)]
+pub enum HeapCell {
+    Object(TeaObject),
+    Array(TeaArray),
+}
+
+impl HeapCell {
+    fn children(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        match self {
+            HeapCell::Object(object) => Box::new(object.entries.values().map(|entry| &entry.0)),
+            HeapCell::Array(array) => Box::new(array.items.iter()),
+        }
+    }
+}
+
+/// When the garbage collector runs.
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /// The number of live cells that triggers the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one is triggered once the live cells grow by this factor,
+    /// which must be greater than 1.
+    pub growth_factor: f64,
+}
+
+impl GcConfig {
+    /// Checks that the growth factor is greater than 1. Otherwise the next collection would be
+    /// due as soon as the last one finished, and the heap would be collected before every
+    /// instruction.
+    pub fn validate(&self) -> Result<(), NotAllowed> {
+        if self.growth_factor > 1. {
+            Ok(())
+        } else {
+            Err(NotAllowed(format!(
+                "a GC growth factor of {}, which must be greater than 1",
+                self.growth_factor
+            )))
+        }
+    }
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 1024,
+            growth_factor: 2.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Cells currently allocated.
+    pub live: usize,
+    /// Cells allocated over the lifetime of the heap.
+    pub allocated: usize,
+    /// Cells freed over the lifetime of the heap.
+    pub freed: usize,
+    pub collections: usize,
+    /// The number of live cells at which the next collection runs.
+    pub next_collection: usize,
+}
+
+/// Storage for objects and arrays. Values refer to cells by handle, so every copy of an object
+/// or array value sees the same entries.
+///
+/// Cells are reclaimed by a mark-and-sweep collector, rooted at the scopes of every active call.
+#[derive(Debug)]
+pub struct Heap {
+    cells: Vec<Option<HeapCell>>,
+    free: Vec<u32>,
+    config: GcConfig,
+    stats: HeapStats,
+}
+
+impl Heap {
+    /// # Panics
+    ///
+    /// If `config` is not valid, as checked by [`GcConfig::validate`].
+    pub fn with_config(config: GcConfig) -> Self {
+        config.validate().unwrap_or_else(|e| e.raise());
+
+        Heap {
+            cells: Vec::new(),
+            free: Vec::new(),
+            config,
+            stats: HeapStats {
+                live: 0,
+                allocated: 0,
+                freed: 0,
+                collections: 0,
+                next_collection: config.initial_threshold,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.stats.live >= self.stats.next_collection
+    }
+
+    /// Frees every cell that cannot be reached from `scope`, its parents, or their stacks.
+    pub(crate) fn collect(&mut self, scope: &Scope) {
+        let mut marked = vec![false; self.cells.len()];
+        let mut pending: Vec<u32> = scope.roots().iter().filter_map(|v| v.handle()).collect();
+
+        while let Some(handle) = pending.pop() {
+            match marked.get_mut(handle as usize) {
+                Some(mark) if !*mark => *mark = true,
+                _ => continue,
+            }
+
+            if let Some(Some(cell)) = self.cells.get(handle as usize) {
+                pending.extend(cell.children().filter_map(|v| v.handle()));
+            }
+        }
+
+        for (handle, mark) in marked.into_iter().enumerate() {
+            if !mark && self.cells[handle].take().is_some() {
+                self.free.push(handle as u32);
+                self.stats.live -= 1;
+                self.stats.freed += 1;
+            }
+        }
+
+        self.stats.collections += 1;
+        self.stats.next_collection = self
+            .config
+            .initial_threshold
+            .max((self.stats.live as f64 * self.config.growth_factor) as usize)
+            .max(self.stats.live + 1);
+    }
+
+    fn alloc(&mut self, cell: HeapCell) -> u32 {
+        self.stats.live += 1;
+        self.stats.allocated += 1;
+
+        if let Some(handle) = self.free.pop() {
+            self.cells[handle as usize] = Some(cell);
+
+            handle
+        } else {
+            self.cells.push(Some(cell));
+
+            (self.cells.len() - 1) as u32
+        }
+    }
+
+    pub fn alloc_object(&mut self, object: TeaObject) -> Value {
+        Value::from_handle(ValueKind::Object, self.alloc(HeapCell::Object(object)))
+    }
+
+    pub fn alloc_array(&mut self, array: TeaArray) -> Value {
+        Value::from_handle(ValueKind::Array, self.alloc(HeapCell::Array(array)))
+    }
+
+    /// Moves object and array literals pushed from bytecode onto the heap, including any nested
+    /// inside them. Other values are returned unchanged.
+    pub(crate) fn instantiate(&mut self, literal: &Value) -> Result<Value, VmError> {
+        match literal.kind {
+            ValueKind::Object => {
+                let mut object = literal.as_tea_object()?;
+
+                for entry in object.entries.values_mut() {
+                    entry.0 = self.instantiate(&entry.0)?;
+                }
+
+                Ok(self.alloc_object(object))
+            }
+
+            ValueKind::Array => {
+                let mut array = literal.as_tea_array()?;
+
+                for item in array.items.iter_mut() {
+                    *item = self.instantiate(item)?;
+                }
+
+                Ok(self.alloc_array(array))
+            }
+
+            _ => Ok(literal.clone()),
+        }
+    }
+
+    /// Compares two values structurally. Values of different kinds are never equal, numbers
+    /// follow IEEE 754 (so NaN is unequal to everything, itself included), and objects and arrays
+    /// are equal when their entries are, regardless of the order object entries were added in.
+    pub fn equals(&self, left: &Value, right: &Value) -> bool {
+        self.equals_inner(left, right, &mut vec![])
+    }
+
+    /// `visiting` holds the pairs of cells being compared further up, which are assumed equal so
+    /// that cyclic structures terminate.
+    fn equals_inner(&self, left: &Value, right: &Value, visiting: &mut Vec<(u32, u32)>) -> bool {
+        if left.kind != right.kind {
+            return false;
+        }
+
+        match left.kind {
+            ValueKind::Number => match (left.as_tea_number(), right.as_tea_number()) {
+                (Ok(left), Ok(right)) => left.value() == right.value(),
+                _ => false,
+            },
+
+            ValueKind::Object | ValueKind::Array => {
+                let (Some(l), Some(r)) = (left.handle(), right.handle()) else {
+                    return false;
+                };
+
+                if visiting.contains(&(l, r)) {
+                    return true;
+                }
+
+                visiting.push((l, r));
+
+                let equal = match (self.cell(l), self.cell(r)) {
+                    (Ok(HeapCell::Object(l)), Ok(HeapCell::Object(r))) => {
+                        l.entries.len() == r.entries.len()
+                            && l.entries.iter().all(|(key, (value, _))| {
+                                r.entries
+                                    .get(key)
+                                    .is_some_and(|(other, _)| self.equals_inner(value, other, visiting))
+                            })
+                    }
+
+                    (Ok(HeapCell::Array(l)), Ok(HeapCell::Array(r))) => {
+                        l.items.len() == r.items.len()
+                            && l.items
+                                .iter()
+                                .zip(r.items.iter())
+                                .all(|(value, other)| self.equals_inner(value, other, visiting))
+                    }
+
+                    _ => false,
+                };
+
+                visiting.pop();
+
+                equal
+            }
+
+            _ => left.data == right.data,
+        }
+    }
+
+    pub(crate) fn cell(&self, handle: u32) -> Result<&HeapCell, DeallocatedError> {
+        self.cells
+            .get(handle as usize)
+            .and_then(|cell| cell.as_ref())
+            .ok_or_else(|| DeallocatedError(format!("Heap::cell#{handle}")))
+    }
+
+    fn cell_mut(&mut self, handle: u32) -> Result<&mut HeapCell, DeallocatedError> {
+        self.cells
+            .get_mut(handle as usize)
+            .and_then(|cell| cell.as_mut())
+            .ok_or_else(|| DeallocatedError(format!("Heap::cell#{handle}")))
+    }
+
+    pub fn object(&self, value: &Value) -> Result<&TeaObject, VmError> {
+        match value.handle().map(|handle| self.cell(handle)) {
+            Some(Ok(HeapCell::Object(object))) => Ok(object),
+            Some(Err(e)) => Err(e.into()),
+            _ => Err(CannotConstruct("heap::TeaObject".to_string(), value).into()),
+        }
+    }
+
+    pub fn object_mut(&mut self, value: &Value) -> Result<&mut TeaObject, VmError> {
+        match value.handle().map(|handle| self.cell_mut(handle)) {
+            Some(Ok(HeapCell::Object(object))) => Ok(object),
+            Some(Err(e)) => Err(e.into()),
+            _ => Err(CannotConstruct("heap::TeaObject".to_string(), value).into()),
+        }
+    }
+
+    pub fn array(&self, value: &Value) -> Result<&TeaArray, VmError> {
+        match value.handle().map(|handle| self.cell(handle)) {
+            Some(Ok(HeapCell::Array(array))) => Ok(array),
+            Some(Err(e)) => Err(e.into()),
+            _ => Err(CannotConstruct("heap::TeaArray".to_string(), value).into()),
+        }
+    }
+
+    pub fn array_mut(&mut self, value: &Value) -> Result<&mut TeaArray, VmError> {
+        match value.handle().map(|handle| self.cell_mut(handle)) {
+            Some(Ok(HeapCell::Array(array))) => Ok(array),
+            Some(Err(e)) => Err(e.into()),
+            _ => Err(CannotConstruct("heap::TeaArray".to_string(), value).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes;
+    use crate::ordered_map::OrderedMap;
+    use crate::test_util::{array, eval, function, load, num, object, push, store, str};
+    use crate::values::{SpecificValue, TeaNumber};
+    use crate::vm::Vm;
+
+    fn empty_array(heap: &mut Heap) -> Value {
+        heap.alloc_array(TeaArray::new(Vec::new()))
+    }
+
+    #[test]
+    fn collect_frees_only_unreachable_cells() {
+        let mut heap = Heap::with_config(GcConfig::default());
+        let mut scope = Scope::new_global(&mut heap);
+        let globals = heap.stats().live;
+
+        let kept = empty_array(&mut heap);
+        let inner = empty_array(&mut heap);
+        let outer = heap.alloc_array(TeaArray::new(vec![inner.clone()]));
+
+        empty_array(&mut heap);
+        scope.set_var(&100, &kept);
+        scope.push(&outer);
+        heap.collect(&scope);
+
+        assert_eq!(heap.stats().live, globals + 3);
+        assert_eq!(heap.stats().freed, 1);
+        assert!(heap.array(&kept).is_ok());
+        assert!(heap.array(&inner).is_ok());
+    }
+
+    #[test]
+    fn collect_frees_unreachable_cycles_and_keeps_reachable_ones() {
+        let mut heap = Heap::with_config(GcConfig::default());
+        let mut scope = Scope::new_global(&mut heap);
+        let globals = heap.stats().live;
+
+        for reachable in [true, false] {
+            let a = heap.alloc_object(TeaObject::new(OrderedMap::new()));
+            let b = heap.alloc_array(TeaArray::new(vec![a.clone()]));
+
+            heap.object_mut(&a).unwrap().entries.insert("b".into(), (b.clone(), 0));
+            heap.object_mut(&a).unwrap().entries.insert("self".into(), (a.clone(), 0));
+
+            if reachable {
+                scope.set_var(&100, &b);
+            }
+        }
+
+        heap.collect(&scope);
+
+        assert_eq!(heap.stats().live, globals + 2);
+        assert_eq!(heap.stats().freed, 2);
+    }
+
+    #[test]
+    fn freed_handles_are_reused() {
+        let mut heap = Heap::with_config(GcConfig::default());
+        let scope = Scope::new_global(&mut heap);
+        let garbage = empty_array(&mut heap).handle();
+
+        heap.collect(&scope);
+
+        assert_eq!(empty_array(&mut heap).handle(), garbage);
+    }
+
+    #[test]
+    fn values_held_by_callers_survive_collections_in_the_callee() {
+        let mut vm = Vm::with_gc_config(GcConfig {
+            initial_threshold: 1,
+            growth_factor: 2.,
+        });
+
+        let garbage = [push(array(&[num(0.)])), vec![opcodes::POP]].concat().repeat(50);
+        let callee = [garbage, vec![opcodes::RET]].concat();
+        let code = [
+            push(array(&[num(1.), array(&[num(2.)])])),
+            push(function(&callee)),
+            vec![opcodes::CALL],
+            store(100),
+        ]
+        .concat();
+
+        vm.run(&code).unwrap_or_else(|e| e.raise());
+
+        let stats = vm.heap_stats();
+        let kept = vm.get_global(100).unwrap();
+        let items = &vm.heap().array(&kept).unwrap().items;
+        let nested = &vm.heap().array(&items[1]).unwrap().items;
+
+        assert!(stats.collections > 1);
+        assert!(stats.freed > 0);
+        assert_eq!(items[0].as_tea_number().ok().map(|n| n.value()), Some(1.));
+        assert_eq!(nested[0].as_tea_number().ok().map(|n| n.value()), Some(2.));
+    }
+
+    #[test]
+    fn the_next_collection_is_past_the_live_cells() {
+        let mut heap = Heap::with_config(GcConfig {
+            initial_threshold: 1,
+            growth_factor: 1.01,
+        });
+        let mut scope = Scope::new_global(&mut heap);
+        let kept = empty_array(&mut heap);
+
+        scope.push(&kept);
+        heap.collect(&scope);
+
+        assert!(heap.stats().next_collection > heap.stats().live);
+        assert!(!heap.should_collect());
+    }
+
+    #[test]
+    fn growth_factors_of_one_or_less_are_rejected() {
+        for growth_factor in [1., 0.5, f64::NAN] {
+            let config = GcConfig {
+                initial_threshold: 1,
+                growth_factor,
+            };
+
+            assert!(config.validate().is_err());
+        }
+
+        assert!(GcConfig::default().validate().is_ok());
+    }
+
+    /// Whether EQ finds the values that `code` pushes equal.
+    fn equal(code: &[Vec<u8>]) -> Option<bool> {
+        let value = eval(&[code.concat(), vec![opcodes::EQ]].concat()).ok()?;
+
+        value.as_tea_bool().ok().map(|b| b.value())
+    }
+
+    #[test]
+    fn values_are_compared_by_their_contents() {
+        let nested = || array(&[num(1.), array(&[str("two")])]);
+
+        assert_eq!(equal(&[push(nested()), push(nested())]), Some(true));
+        let (one_two, two_one) = (array(&[num(1.), num(2.)]), array(&[num(2.), num(1.)]));
+
+        assert_eq!(equal(&[push(one_two), push(two_one)]), Some(false));
+        assert_eq!(equal(&[push(num(f64::NAN)), push(num(f64::NAN))]), Some(false));
+        assert_eq!(equal(&[push(num(1.)), push(str("1"))]), Some(false));
+
+        let ab = object(&[("a", num(1.)), ("b", nested())]);
+        let ba = object(&[("b", nested()), ("a", num(1.))]);
+
+        assert_eq!(equal(&[push(ab.clone()), push(ba)]), Some(true));
+        assert_eq!(equal(&[push(ab), push(object(&[("a", num(1.))]))]), Some(false));
+    }
+
+    #[test]
+    fn values_that_contain_themselves_can_be_compared() {
+        let cycle = |idx: u32| {
+            [
+                push(array(&[num(0.)])),
+                store(idx),
+                load(idx),
+                push(num(0.)),
+                load(idx),
+                vec![opcodes::SET_DYN],
+            ]
+            .concat()
+        };
+
+        assert_eq!(equal(&[cycle(200), cycle(201), load(200), load(201)]), Some(true));
+    }
+
+    #[test]
+    fn objects_keep_entries_in_the_order_they_were_added() {
+        let mut heap = Heap::with_config(GcConfig::default());
+        let object = heap.alloc_object(TeaObject::new(OrderedMap::new()));
+        let keys = |object: &TeaObject| -> Vec<String> {
+            object.entries.iter().map(|(key, _)| key.clone()).collect()
+        };
+
+        for (key, n) in [("b", 1.), ("c", 2.), ("a", 3.), ("c", 4.)] {
+            let entries = &mut heap.object_mut(&object).unwrap().entries;
+
+            entries.insert(key.into(), (TeaNumber(n).to_value(), 0));
+        }
+
+        let entries = heap.object(&object).unwrap();
+        let literal = TeaObject::new(entries.entries.clone()).to_value();
+
+        assert_eq!(keys(entries), ["b", "c", "a"]);
+        assert_eq!(literal.as_tea_object().map(|object| keys(&object)).ok(), Some(keys(entries)));
+        assert_eq!(literal.data, TeaObject::new(entries.entries.clone()).to_value().data);
+    }
+}
@@ -0,0 +1,33 @@
+// Lets the derive macros, which name `::teavm_rs`, be used inside this crate's tests.
+#[cfg(test)]
+extern crate self as teavm_rs;
+
+pub mod backend;
+pub mod convert;
+pub mod errors;
+pub mod heap;
+pub mod opcodes;
+pub mod permissions;
+pub mod values;
+pub mod vm;
+
+mod display;
+mod env;
+mod files;
+mod format;
+mod fs;
+mod math;
+mod ordered_map;
+mod random;
+mod scope;
+#[cfg(test)]
+mod test_util;
+
+pub use convert::{FromTea, IntoTea};
+pub use display::DisplayValue;
+pub use files::StdoutBuffering;
+pub use errors::VmError;
+pub use ordered_map::OrderedMap;
+pub use scope::{ARGS_VAR, ENV_VAR, FS_VAR, HOST_VAR, IO_VAR, MATH_VAR, RANDOM_VAR};
+pub use teavm_derive::{FromTea, IntoTea};
+pub use vm::Vm;
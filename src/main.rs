@@ -1,532 +1,103 @@
-#![feature(slice_pattern)]
-
-extern crate core;
-
-use crate::errors::{DeallocatedError, NoValueError, NotAllowed, RuntimeError};
-use crate::scope::Scope;
-use crate::values::{SpecificValue, TeaBool, TeaNumber, TeaStr, Value};
-use std::{fs, io::stdin};
-use std::collections::HashMap;
-
-mod errors;
-mod opcodes;
-mod scope;
-mod values;
-
-fn combine_u8_to_u32(bytes: &[u8], offset: usize) -> Option<u32> {
-    if offset >= bytes.len() || offset + 4 > bytes.len() {
-        return None;
-    }
-
-    Some(u32::from_be_bytes(
-        bytes[offset..offset + 4]
-            .try_into()
-            .expect("Failed to convert bytes to u32"),
-    ))
-}
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, process};
+use teavm_rs::errors::RuntimeError;
+use teavm_rs::heap::GcConfig;
+use teavm_rs::permissions::{PathGrant, Permissions};
+use teavm_rs::{StdoutBuffering, Vm};
 
 fn main() {
     let bytes = fs::read("src/test.bin").unwrap();
 
-    run(&bytes, &mut Scope::new_global());
-}
-
-fn run(bytes: &Vec<u8>, scope: *mut Scope) {
-    let mut pc = 0usize;
-    let mut labels = HashMap::new();
-
-    while pc < bytes.len() {
-        unsafe {
-            let opcode = bytes[pc];
-
-            match opcode {
-                opcodes::PUSH => {
-                    pc += 1;
-
-                    let value = Value::from_bytes(bytes.split_at(pc).1);
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&value);
-
-                    pc += value.data_len.clone() as usize + 2;
-                }
-
-                opcodes::STORE => {
-                    pc += 1;
-
-                    let value = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("Store::stack::value".into()).raise());
-
-                    let values = &bytes[pc..pc + 4];
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .set_var(&combine_u8_to_u32(values, 0).unwrap(), &value);
-
-                    pc += 4;
-                }
-
-                opcodes::LOAD => {
-                    pc += 1;
-
-                    let values = &bytes[pc..pc + 4];
-
-                    if let Some(idx) = combine_u8_to_u32(values, 0) {
-                        let value = scope
-                            .as_mut()
-                            .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                            .get_var(&idx)
-                            .clone()
-                            .unwrap_or_else(|| {
-                                NoValueError(format!("Load::variable(idx = {})", idx)).raise()
-                            });
-
-                        scope
-                            .as_mut()
-                            .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                            .push(&value)
-                    }
-
-                    pc += 4;
-                }
-
-                opcodes::NADD => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NADD::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NADD::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&Value::from_specific(&TeaNumber(
-                            left.value() + right.value(),
-                        )))
-                }
-
-                opcodes::NSUB => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NSUB::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NSUB::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&(left - right).to_value())
-                }
-
-                opcodes::NMUL => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NMUL::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NMUL::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&(left * right).to_value())
-                }
-
-                opcodes::NDIV => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NDIV::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NDIV::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&(left / right).to_value())
-                }
-
-                opcodes::NMOD => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NMOD::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NMOD::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&(left % right).to_value())
-                }
-
-                opcodes::NPOW => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NPOW::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NPOW::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&left.pow(right).to_value())
-                }
-
-                opcodes::SMUL => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("SMUL::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("SMUL::stack::left".to_string()).raise())
-                        .as_tea_string()
-                        .unwrap_or_else(|e| e.raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&Value::from_specific(&TeaStr(
-                            left.value().repeat(right.value() as usize),
-                        )))
-                }
-
-                opcodes::CALL => {
-                    pc += 1;
-
-                    let f = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("CALL::stack::fn".to_string()).raise())
-                        .as_tea_function()
-                        .unwrap_or_else(|e| e.raise());
-
-                    run(
-                        &f.code.as_ref().to_vec(),
-                        &mut Scope::new(Option::from(scope)).clone().clone(),
-                    )
-                }
-
-                opcodes::GET => {
-                    pc += 1;
-
-                    let obj = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("GET::stack::obj".to_string()).raise())
-                        .as_tea_object()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let key_len = bytes[pc];
-
-                    pc += 1;
-
-                    let key = String::from_utf8(bytes[pc..=pc + (key_len - 1) as usize].to_vec())
-                        .unwrap();
-
-                    pc += key_len as usize;
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(
-                            &obj.entries
-                                .get(&key)
-                                .unwrap_or_else(|| {
-                                    NoValueError(format!("GET::stack::Object::{key}")).raise()
-                                })
-                                .to_owned()
-                                .0,
-                        );
-                }
-
-                opcodes::WRITE => {
-                    pc += 1;
-
-                    let value = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("WRITE::stack::value".to_string()).raise())
-                        .as_tea_string()
-                        .unwrap_or_else(|e| e.raise());
-
-                    let fd = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("WRITE::stack::fd".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise())
-                        .0 as u32;
-
-                    match fd {
-                        0 => {
-                            print!("{}", value.value())
-                        }
-
-                        1 => NotAllowed("writing to stdin".to_string()).raise(),
-
-                        fd => {
-                            unsafe { scope.as_mut() }
-                                .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                                .write_fd(&fd, value.0.as_bytes())
-                                .unwrap_or_else(|e| e.raise());
-                        }
-                    }
-                }
-
-                opcodes::READLN => {
-                    pc += 1;
-
-                    let fd = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("READ::stack::fd".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise())
-                        .0 as u32;
-
-                    match fd {
-                        0 => NotAllowed("reading from stdout".to_string()).raise(),
-
-                        1 => {
-                            let str: *mut String = &mut String::new();
-
-                            if let Ok(_) = stdin().read_line(unsafe { str.as_mut() }.unwrap()) {
-                                scope
-                                    .as_mut()
-                                    .unwrap_or_else(|| {
-                                        DeallocatedError("Scope::global".into()).raise()
-                                    })
-                                    .push(
-                                        &TeaStr(unsafe { str.as_ref() }.unwrap().clone())
-                                            .to_value(),
-                                    )
-                            };
-                        }
-
-                        fd => {
-                            unsafe { scope.as_mut() }
-                                .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                                .read_fd(&fd)
-                                .unwrap_or_else(|e| e.raise());
-                        }
-                    }
-                }
-                
-                opcodes::MARKER => {
-                    pc += 1;
-
-                    let values = &bytes[pc..pc + 4];
-                    let idx = combine_u8_to_u32(values, 0).unwrap();
-                    
-                    pc += 4;
-                    
-                    labels.insert(idx, pc);
-                }
-                
-                opcodes::GOTO => {
-                    pc += 1;
-
-                    let values = &bytes[pc..pc + 4];
-                    let idx = &combine_u8_to_u32(values, 0).unwrap();
-
-                    pc = labels[idx];
-                }
-                
-                opcodes::GOTO_IF => {
-                    pc += 1;
-                    
-                    let cond =
-                        unsafe { scope.as_mut() }
-                            .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                            .pop()
-                            .unwrap_or_else(|| NoValueError("GOTO_IF::stack::cond".to_string()).raise())
-                            .as_tea_bool();
-
-                    if cond.value() {
-                        let values = &bytes[pc..pc + 4];
-                        let idx = &combine_u8_to_u32(values, 0).unwrap();
-
-                        pc = labels[idx];
-                    } else {
-                        pc += 4;
-                    }
-                }
-                
-                opcodes::EQ => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("EQ::stack::right".to_string()).raise());
-
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("EQ::stack::left".to_string()).raise());
-
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&TeaBool(left.data == right.data).to_value())
-                }
-
-                opcodes::NGT => {
-                    pc += 1;
-
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NGT::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
+    let mut gc_config = GcConfig::default();
+    let mut gc_stats = false;
+    let mut buffering = StdoutBuffering::default();
+    let mut permissions = Permissions::default();
+    let mut audit = false;
+    let mut seed = None;
+    let mut args = env::args().skip(1);
+    let mut program_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            program_args.extend(args.by_ref());
+        } else if let Some(threshold) = arg.strip_prefix("--gc-threshold=") {
+            gc_config.initial_threshold = threshold.parse().expect("Invalid --gc-threshold.");
+        } else if let Some(growth) = arg.strip_prefix("--gc-growth=") {
+            gc_config.growth_factor = growth.parse().expect("Invalid --gc-growth.");
+        } else if arg == "--gc-stats" {
+            gc_stats = true;
+        } else if let Some(mode) = arg.strip_prefix("--stdout-buffer=") {
+            buffering = match mode {
+                "none" => StdoutBuffering::Unbuffered,
+                "line" => StdoutBuffering::Line,
+                size => StdoutBuffering::Full(size.parse().expect("Invalid --stdout-buffer.")),
+            };
+        } else if arg == "--allow-all" {
+            permissions = Permissions::all();
+        } else if arg == "--allow-read" {
+            permissions.read = PathGrant::All;
+        } else if let Some(path) = arg.strip_prefix("--allow-read=") {
+            permissions.read.allow(path);
+        } else if arg == "--allow-write" {
+            permissions.write = PathGrant::All;
+        } else if let Some(path) = arg.strip_prefix("--allow-write=") {
+            permissions.write.allow(path);
+        } else if arg == "--allow-env" {
+            permissions.env = true;
+        } else if arg == "--deny-stdin" {
+            permissions.stdin = false;
+        } else if arg == "--deny-stdout" {
+            permissions.stdout = false;
+        } else if arg == "--deny-stderr" {
+            permissions.stderr = false;
+        } else if arg == "--audit" {
+            audit = true;
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            seed = Some(value.parse().expect("Invalid --seed."));
+        } else if arg.starts_with("--") {
+            panic!("Unknown option {arg}.");
+        } else {
+            program_args.push(arg);
+        }
+    }
 
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NGT::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
+    if let Err(e) = gc_config.validate() {
+        e.raise()
+    }
 
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&TeaBool(left.value() > right.value()).to_value())
-                }
+    let mut vm = Vm::with_gc_config(gc_config);
 
-                opcodes::NLT => {
-                    pc += 1;
+    vm.set_stdout_buffering(buffering).unwrap_or_else(|e| e.raise());
+    vm.set_permissions(permissions);
+    vm.set_args(&program_args);
+    // Without --seed, each run gets its own sequence.
+    vm.seed_random(seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+    }));
 
-                    let right = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NLT::stack::right".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
+    let result = vm.run(&bytes);
 
-                    let left = scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .pop()
-                        .unwrap_or_else(|| NoValueError("NLT::stack::left".to_string()).raise())
-                        .as_tea_number()
-                        .unwrap_or_else(|e| e.raise());
+    if audit {
+        for access in vm.audit_log() {
+            eprintln!("audit: {access}");
+        }
+    }
 
-                    scope
-                        .as_mut()
-                        .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                        .push(&TeaBool(left.value() < right.value()).to_value())
-                }
+    if let Err(e) = result {
+        e.raise()
+    }
 
-                opcodes::PRINT => {
-                    pc += 1;
+    if gc_stats {
+        vm.collect_garbage().unwrap_or_else(|e| e.raise());
 
-                    println!(
-                        "{:#?}",
-                        scope
-                            .as_mut()
-                            .unwrap_or_else(|| DeallocatedError("Scope::global".into()).raise())
-                            .pop()
-                    );
-                }
+        eprintln!("{:#?}", vm.heap_stats());
+    }
 
-                _ => (),
-            }
-        }
+    if let Some(status) = vm.exit_status() {
+        // Exiting skips destructors, so buffered output is written first.
+        drop(vm);
+        process::exit(status);
     }
 }
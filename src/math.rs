@@ -0,0 +1,134 @@
+use crate::errors::VmError;
+use crate::scope::MATH_VAR;
+use crate::values::{SpecificValue, TeaNumber};
+use crate::vm::Vm;
+
+type Unary = fn(f64) -> f64;
+type Binary = fn(f64, f64) -> f64;
+
+/// Functions of one number.
+const UNARY: [(&str, Unary); 18] = [
+    ("sqrt", f64::sqrt),
+    ("cbrt", f64::cbrt),
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("asin", f64::asin),
+    ("acos", f64::acos),
+    ("atan", f64::atan),
+    ("exp", f64::exp),
+    ("exp2", f64::exp2),
+    ("log", f64::ln),
+    ("log2", f64::log2),
+    ("log10", f64::log10),
+    ("floor", f64::floor),
+    ("ceil", f64::ceil),
+    ("round", f64::round),
+    ("trunc", f64::trunc),
+    ("abs", f64::abs),
+];
+
+/// Functions of two numbers, taken in the order they were pushed.
+const BINARY: [(&str, Binary); 5] = [
+    ("atan2", f64::atan2),
+    ("pow", f64::powf),
+    ("hypot", f64::hypot),
+    ("min", f64::min),
+    ("max", f64::max),
+];
+
+/// Fills in the functions of the `math` object of the global scope, next to its constants `pi`,
+/// `tau`, `e`, `inf` and `nan`.
+///
+/// - `sqrt`, `cbrt`, `exp`, `exp2` and `abs` take one number, as do `sin`, `cos` and `tan`, in
+///   radians, and their inverses `asin`, `acos` and `atan`.
+/// - `log` is the natural logarithm, next to `log2` and `log10`.
+/// - `floor`, `ceil`, `round` and `trunc` push a whole number. `round` rounds halves away from
+///   zero.
+/// - `atan2(y, x)`, `pow(base, exp)` and `hypot(x, y)` take two numbers.
+/// - `min(a, b)` and `max(a, b)` ignore a NaN argument in favour of the other.
+/// - `clamp(x, min, max)` limits `x` to between `min` and `max`. It is an error if `min` is
+///   greater than `max`, or either is NaN.
+///
+/// Results outside the domain of a function, such as `sqrt(-1)`, are NaN.
+pub(crate) fn register(vm: &mut Vm) -> Result<(), VmError> {
+    for (name, f) in UNARY {
+        vm.register_native_in(MATH_VAR, name, 1, move |_vm, args| {
+            let x = args[0].as_tea_number()?.value();
+
+            Ok(TeaNumber(f(x)).to_value())
+        })?;
+    }
+
+    for (name, f) in BINARY {
+        vm.register_native_in(MATH_VAR, name, 2, move |_vm, args| {
+            let a = args[0].as_tea_number()?.value();
+            let b = args[1].as_tea_number()?.value();
+
+            Ok(TeaNumber(f(a, b)).to_value())
+        })?;
+    }
+
+    vm.register_native_in(MATH_VAR, "clamp", 3, |_vm, args| {
+        let x = args[0].as_tea_number()?.value();
+        let min = args[1].as_tea_number()?.value();
+        let max = args[2].as_tea_number()?.value();
+
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(VmError::Native(format!("math.clamp: {min} to {max} is not a range")));
+        }
+
+        Ok(TeaNumber(x.clamp(min, max)).to_value())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes;
+    use crate::test_util::{get, load, num, number, push};
+
+    /// Calls `math.name` with `args`.
+    fn call(name: &str, args: &[f64]) -> Option<f64> {
+        let args: Vec<u8> = args.iter().flat_map(|arg| push(num(*arg))).collect();
+
+        number(&[args, load(MATH_VAR), get(name), vec![opcodes::CALL]].concat()).ok()
+    }
+
+    #[test]
+    fn functions_take_their_arguments_in_the_order_they_were_pushed() {
+        assert_eq!(call("sqrt", &[9.]), Some(3.));
+        assert_eq!(call("pow", &[2., 10.]), Some(1024.));
+        assert_eq!(call("atan2", &[1., 0.]), Some(std::f64::consts::FRAC_PI_2));
+        assert_eq!(call("hypot", &[3., 4.]), Some(5.));
+        assert_eq!(call("log10", &[1000.]), Some(3.));
+        assert_eq!(call("abs", &[-2.5]), Some(2.5));
+        assert_eq!(call("trunc", &[-2.5]), Some(-2.));
+        assert!(call("sqrt", &[-1.]).is_some_and(f64::is_nan));
+        assert_eq!(call("sqrt", &[]), None);
+    }
+
+    #[test]
+    fn round_min_and_max_handle_the_edge_cases() {
+        assert_eq!(call("round", &[2.5]), Some(3.));
+        assert_eq!(call("round", &[-2.5]), Some(-3.));
+        assert_eq!(call("min", &[f64::NAN, 1.]), Some(1.));
+        assert_eq!(call("max", &[2., f64::NAN]), Some(2.));
+    }
+
+    #[test]
+    fn clamp_needs_a_range() {
+        assert_eq!(call("clamp", &[5., 0., 3.]), Some(3.));
+        assert_eq!(call("clamp", &[-1., 0., 3.]), Some(0.));
+        assert_eq!(call("clamp", &[1., 3., 0.]), None);
+        assert_eq!(call("clamp", &[1., f64::NAN, 3.]), None);
+    }
+
+    #[test]
+    fn constants_sit_next_to_the_functions() {
+        let constant = |name: &str| number(&[load(MATH_VAR), get(name)].concat()).ok();
+
+        assert_eq!(constant("tau"), Some(std::f64::consts::TAU));
+        assert_eq!(constant("inf"), Some(f64::INFINITY));
+    }
+}
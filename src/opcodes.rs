@@ -1,27 +1,631 @@
+/// Pushes a value. Object and array literals are allocated on the heap, and the
+/// value pushed refers to them.
+///
+/// PUSH (0) KIND (u8) LEN (u16) DATA (LEN bytes) ( -- v )
 pub const PUSH: u8 = 0u8;
+
+/// Pops a value and prints it to stdout, formatted as [`TOSTR`] would, followed by a newline.
+///
+/// PRINT (1) ( v -- )
 pub const PRINT: u8 = 1u8;
+
+/// Pops two numbers and pushes their sum.
+///
+/// NADD (2) ( a b -- a+b )
 pub const NADD: u8 = 2u8;
+
+/// Pops two numbers and pushes the left minus the right.
+///
+/// NSUB (3) ( a b -- a-b )
 pub const NSUB: u8 = 3u8;
+
+/// Pops two numbers and pushes their product.
+///
+/// NMUL (4) ( a b -- a*b )
 pub const NMUL: u8 = 4u8;
+
+/// Pops two numbers and pushes the left divided by the right. Dividing by 0 gives an infinity,
+/// or NaN for 0 / 0.
+///
+/// NDIV (5) ( a b -- a/b )
 pub const NDIV: u8 = 5u8;
+
+/// Pops two numbers and pushes the remainder of dividing the left by the right, which has the
+/// sign of the left.
+///
+/// NMOD (6) ( a b -- a%b )
 pub const NMOD: u8 = 6u8;
+
+/// Pops two numbers and pushes the left raised to the power of the right.
+///
+/// NPOW (7) ( a b -- a**b )
 pub const NPOW: u8 = 7u8;
+
+/// Pops a value into the variable at IDX.
+///
+/// STORE (8) IDX (u32) ( v -- )
 pub const STORE: u8 = 8u8;
+
+/// Pushes the variable at IDX.
+///
+/// LOAD (9) IDX (u32) ( -- v )
 pub const LOAD: u8 = 9u8;
+
+/// Pops a string and a whole number and pushes the string repeated that many times.
+///
+/// SMUL (10) ( s n -- s*n )
 pub const SMUL: u8 = 10u8;
+
+/// Stops the function that is running. Returning from the top level ends the program.
+///
+/// RET (11) ( -- )
 pub const RET: u8 = 11u8;
+
+/// Pops a function and calls it in a new scope. Whatever a bytecode function leaves on its stack
+/// is discarded. A native function registered by the embedder is passed as many values from the
+/// top of the stack as it takes, and they are replaced by its result.
+///
+/// CALL (12) ( f -- ) or, for a native function, ( a1 .. aN f -- r )
 pub const CALL: u8 = 12u8;
+
+/// Pops an object and pushes the value stored under KEY.
+///
+/// GET (13) KEY_LEN (u16) KEY (KEY_LEN bytes of UTF-8) ( obj -- v )
 pub const GET: u8 = 13u8;
+
+/// Pops a string or bytes and then a descriptor, and writes them to it. Descriptor 0 is stdout,
+/// which is buffered as the embedder chooses, and descriptor 2 is stderr, which is not.
+/// Files opened with [`OPEN`] are written to directly.
+///
+/// WRITE (14) ( fd s -- ) or ( fd b -- )
 pub const WRITE: u8 = 14u8;
+
+/// Pops a descriptor and pushes the next line read from it, including its line ending, or null
+/// at the end of the input. Descriptor 1 is stdin, and files opened with [`OPEN`] can be read
+/// from too.
+///
+/// READLN (15) ( fd -- s )
 pub const READLN: u8 = 15u8;
+
+/// Marks a position that the GOTO family can jump to, identified by IDX.
+///
+/// MARKER (16) IDX (u32) ( -- )
 pub const MARKER: u8 = 16u8;
+
+/// Goes to the marker at IDX. The marker may come before or after the jump.
+///
+/// GOTO (17) IDX (u32) ( -- )
 pub const GOTO: u8 = 17u8;
 
-/// If a boolean on the stack is true, go to the marker at IDX.
-/// 
-/// GOTO_IF (18) IDX (u32)
+/// Pops a boolean, and if it is true, go to the marker at IDX.
+///
+/// GOTO_IF (18) IDX (u32) ( b -- )
 pub const GOTO_IF: u8 = 18u8;
 
+/// Pops two values and pushes whether they are equal. Objects and arrays are compared by
+/// their contents, and NaN is not equal to anything.
+///
+/// EQ (19) ( a b -- a==b )
 pub const EQ: u8 = 19u8;
+
+/// Pops two numbers and pushes whether the left is greater than the right.
+///
+/// NGT (20) ( a b -- a>b )
 pub const NGT: u8 = 20u8;
-pub const NLT: u8 = 21u8;
\ No newline at end of file
+
+/// Pops two numbers and pushes whether the left is less than the right.
+///
+/// NLT (21) ( a b -- a<b )
+pub const NLT: u8 = 21u8;
+
+/// Pops a key, then an object, and pushes the value stored under the key.
+///
+/// Arrays are indexed with a number key instead.
+///
+/// GET_DYN (22) ( obj key -- v )
+pub const GET_DYN: u8 = 22u8;
+
+/// Pops a value, a key and an object, and sets the key of the object to the value.
+/// Read-only entries cannot be overwritten.
+///
+/// Arrays are indexed with a number key instead; the index must already exist.
+///
+/// SET_DYN (23) ( obj key v -- )
+pub const SET_DYN: u8 = 23u8;
+
+/// Pops a value and pushes it formatted as a string. Strings are pushed unchanged, and objects
+/// and arrays are written out with their contents.
+///
+/// TOSTR (24) ( v -- s )
+pub const TOSTR: u8 = 24u8;
+
+/// Duplicates the top of the stack.
+///
+/// DUP (25) ( a -- a a )
+pub const DUP: u8 = 25u8;
+
+/// Discards the top of the stack.
+///
+/// POP (26) ( a -- )
+pub const POP: u8 = 26u8;
+
+/// Swaps the top two values.
+///
+/// SWAP (27) ( a b -- b a )
+pub const SWAP: u8 = 27u8;
+
+/// Copies the second value to the top.
+///
+/// OVER (28) ( a b -- a b a )
+pub const OVER: u8 = 28u8;
+
+/// Moves the third value to the top.
+///
+/// ROT (29) ( a b c -- b c a )
+pub const ROT: u8 = 29u8;
+
+/// Pops a boolean and pushes its negation.
+///
+/// NOT (30) ( a -- !a )
+pub const NOT: u8 = 30u8;
+
+/// Pops two booleans and pushes whether both are true.
+///
+/// AND (31) ( a b -- a&&b )
+pub const AND: u8 = 31u8;
+
+/// Pops two booleans and pushes whether either is true.
+///
+/// OR (32) ( a b -- a||b )
+pub const OR: u8 = 32u8;
+
+/// Pops two values and pushes whether they are not equal, the negation of [`EQ`].
+///
+/// NEQ (33) ( a b -- a!=b )
+pub const NEQ: u8 = 33u8;
+
+/// Pops two numbers and pushes whether the left is greater than or equal to the right.
+///
+/// NGE (34) ( a b -- a>=b )
+pub const NGE: u8 = 34u8;
+
+/// Pops two numbers and pushes whether the left is less than or equal to the right.
+///
+/// NLE (35) ( a b -- a<=b )
+pub const NLE: u8 = 35u8;
+
+/// Pops two strings and pushes whether the left sorts after the right. Strings are ordered
+/// by their Unicode scalar values.
+///
+/// SGT (36) ( a b -- a>b )
+pub const SGT: u8 = 36u8;
+
+/// Pops two strings and pushes whether the left sorts before the right.
+///
+/// SLT (37) ( a b -- a<b )
+pub const SLT: u8 = 37u8;
+
+/// Pops two strings and pushes whether the left sorts after or equal to the right.
+///
+/// SGE (38) ( a b -- a>=b )
+pub const SGE: u8 = 38u8;
+
+/// Pops two strings and pushes whether the left sorts before or equal to the right.
+///
+/// SLE (39) ( a b -- a<=b )
+pub const SLE: u8 = 39u8;
+
+/// Pops a boolean, and if it is false, go to the marker at IDX.
+///
+/// GOTO_IF_NOT (40) IDX (u32) ( b -- )
+pub const GOTO_IF_NOT: u8 = 40u8;
+
+/// Pops two whole numbers and pushes their bitwise and.
+///
+/// Bitwise opcodes work on numbers as 32-bit two's complement integers, as in JavaScript, so
+/// every result is exact. Operands must be whole, and those outside the range of an i32 wrap
+/// around modulo 2^32. Results are signed, except for [`USHR`], whose result is unsigned.
+///
+/// BAND (41) ( a b -- a&b )
+pub const BAND: u8 = 41u8;
+
+/// Pops two whole numbers and pushes their bitwise or.
+///
+/// BOR (42) ( a b -- a|b )
+pub const BOR: u8 = 42u8;
+
+/// Pops two whole numbers and pushes their bitwise exclusive or.
+///
+/// BXOR (43) ( a b -- a^b )
+pub const BXOR: u8 = 43u8;
+
+/// Pops a whole number and pushes its bitwise complement.
+///
+/// BNOT (44) ( a -- ~a )
+pub const BNOT: u8 = 44u8;
+
+/// Shifts the left operand left by the right operand. Only the low 5 bits of the shift amount
+/// are used, so shifting by 32 is the same as shifting by 0.
+///
+/// SHL (45) ( a b -- a<<b )
+pub const SHL: u8 = 45u8;
+
+/// Shifts the left operand right by the right operand, keeping its sign. Only the low 5 bits
+/// of the shift amount are used.
+///
+/// SHR (46) ( a b -- a>>b )
+pub const SHR: u8 = 46u8;
+
+/// Shifts the left operand right by the right operand, filling with zeroes, and pushes the
+/// result as an unsigned 32-bit number. Only the low 5 bits of the shift amount are used, so
+/// `a USHR 0` converts `a` to unsigned.
+///
+/// USHR (47) ( a b -- a>>>b )
+pub const USHR: u8 = 47u8;
+
+/// Rotates the 32 bits of the left operand left by the right operand, modulo 32.
+///
+/// ROTL (48) ( a b -- rotl(a, b) )
+pub const ROTL: u8 = 48u8;
+
+/// Rotates the 32 bits of the left operand right by the right operand, modulo 32.
+///
+/// ROTR (49) ( a b -- rotr(a, b) )
+pub const ROTR: u8 = 49u8;
+
+/// Pops two strings and pushes them joined together.
+///
+/// String opcodes count Unicode scalar values rather than bytes, so indices and lengths never
+/// split a UTF-8 sequence.
+///
+/// SCONCAT (50) ( a b -- a+b )
+pub const SCONCAT: u8 = 50u8;
+
+/// Pops a string and pushes its length in characters.
+///
+/// SLEN (51) ( s -- len )
+pub const SLEN: u8 = 51u8;
+
+/// Pops a string and two indices and pushes the characters from START up to END. Indices past
+/// the end of the string are clamped to its length.
+///
+/// SSLICE (52) ( s start end -- s[start..end] )
+pub const SSLICE: u8 = 52u8;
+
+/// Pops a string and an index and pushes the character at that index as a string.
+///
+/// SCHAR_AT (53) ( s idx -- s[idx] )
+pub const SCHAR_AT: u8 = 53u8;
+
+/// Pops a string and a needle and pushes the index of the first occurrence of the needle,
+/// or -1 if it does not occur.
+///
+/// SFIND (54) ( s needle -- idx )
+pub const SFIND: u8 = 54u8;
+
+/// Pops a string and a separator and pushes an array of the parts between separators. An empty
+/// separator splits the string into characters.
+///
+/// SSPLIT (55) ( s sep -- parts )
+pub const SSPLIT: u8 = 55u8;
+
+/// Pops an array of strings and a separator and pushes the strings joined by the separator.
+///
+/// SJOIN (56) ( parts sep -- s )
+pub const SJOIN: u8 = 56u8;
+
+/// Pops a string and pushes it in upper case.
+///
+/// SUPPER (57) ( s -- S )
+pub const SUPPER: u8 = 57u8;
+
+/// Pops a string and pushes it in lower case.
+///
+/// SLOWER (58) ( S -- s )
+pub const SLOWER: u8 = 58u8;
+
+/// Pops a string and pushes it without leading and trailing whitespace.
+///
+/// STRIM (59) ( s -- s )
+pub const STRIM: u8 = 59u8;
+
+/// Pops COUNT values and then a template string, and pushes the template with its placeholders
+/// filled in by the values.
+///
+/// `{}` takes the next value and `{N}` takes value N. A placeholder may be followed by a colon
+/// and `[[fill]align][0][width][.precision][type]`, where align is `<`, `>` or `^` and type is
+/// `x`, `X`, `o` or `b` for a radix, or `e` for exponent notation. `{{` and `}}` are literal
+/// braces.
+///
+/// FORMAT (60) COUNT (u8) ( template v1 .. vCOUNT -- s )
+pub const FORMAT: u8 = 60u8;
+
+/// MODE for conversions that push null when the value cannot be converted.
+pub const ON_FAIL_NULL: u8 = 0u8;
+
+/// MODE for conversions that raise an error when the value cannot be converted.
+pub const ON_FAIL_RAISE: u8 = 1u8;
+
+/// Pops a value and pushes it as a number. Strings are parsed after trimming whitespace, so a
+/// line from READLN converts directly, and booleans become 1 or 0. If the value cannot be
+/// converted, MODE decides between pushing null and raising an error.
+///
+/// TONUM (61) MODE (u8) ( v -- n )
+pub const TONUM: u8 = 61u8;
+
+/// Pops a value and pushes whether it is truthy. False, null, 0, NaN, the empty string and empty
+/// bytes are false, and every other value is true.
+///
+/// TOBOOL (62) ( v -- b )
+pub const TOBOOL: u8 = 62u8;
+
+/// Pops a string and a radix between 2 and 36 and pushes the string parsed as a whole number
+/// in that radix. If it cannot be parsed, MODE decides between pushing null and raising an error.
+///
+/// PARSE_INT (63) MODE (u8) ( s radix -- n )
+pub const PARSE_INT: u8 = 63u8;
+
+/// Pops a value and pushes whether it is null.
+///
+/// ISNULL (64) ( v -- b )
+pub const ISNULL: u8 = 64u8;
+
+/// Pops a value, and if it is null, go to the marker at IDX.
+///
+/// GOTO_IF_NULL (65) IDX (u32) ( v -- )
+pub const GOTO_IF_NULL: u8 = 65u8;
+
+/// If the value on the stack is not null, leave it there and go to the marker at IDX.
+/// Otherwise, pop the null and continue, so the code that follows can push a fallback.
+///
+/// COALESCE (66) IDX (u32) ( v -- v ) or ( null -- )
+pub const COALESCE: u8 = 66u8;
+
+/// Like [`LOAD`], but pushes null if the variable does not exist.
+///
+/// LOAD_OPT (67) IDX (u32) ( -- v )
+pub const LOAD_OPT: u8 = 67u8;
+
+/// Like [`GET`], but pushes null if the key does not exist.
+///
+/// GET_OPT (68) KEY_LEN (u16) KEY (KEY_LEN bytes of UTF-8) ( obj -- v )
+pub const GET_OPT: u8 = 68u8;
+
+/// Like [`GET_DYN`], but pushes null if the key or index does not exist.
+///
+/// GET_DYN_OPT (69) ( obj key -- v )
+pub const GET_DYN_OPT: u8 = 69u8;
+
+/// MODE for opening an existing file for reading.
+pub const OPEN_READ: u8 = 0u8;
+
+/// MODE for opening a file for writing, creating it if needed and emptying it if it exists.
+pub const OPEN_WRITE: u8 = 1u8;
+
+/// MODE for opening a file for writing at its end, creating it if needed.
+pub const OPEN_APPEND: u8 = 2u8;
+
+/// MODE for creating a new file for writing. It is an error if the file already exists.
+pub const OPEN_CREATE: u8 = 3u8;
+
+/// Pops a path and pushes a new descriptor for the file there, opened in MODE. The file stays
+/// open until [`CLOSE`], and descriptors are never reused.
+///
+/// OPEN (70) MODE (u8) ( path -- fd )
+pub const OPEN: u8 = 70u8;
+
+/// Pops a descriptor from [`OPEN`] and closes its file.
+///
+/// CLOSE (71) ( fd -- )
+pub const CLOSE: u8 = 71u8;
+
+/// Pops a descriptor and pushes everything left to read from it, which is empty at the end of
+/// the input.
+///
+/// READ_ALL (72) ( fd -- s )
+pub const READ_ALL: u8 = 72u8;
+
+/// Pops a descriptor and a count and pushes up to COUNT characters read from it as a string, or
+/// null at the end of the input. Fewer characters are pushed when the input ends first. Only
+/// whole characters are read, so one is never split between two READs. It is an error if the
+/// input is not valid UTF-8; use [`READ_BYTES`] for binary input.
+///
+/// READ (73) ( fd count -- s )
+pub const READ: u8 = 73u8;
+
+/// Pops a descriptor and pushes whether there is nothing left to read from it.
+///
+/// EOF (74) ( fd -- b )
+pub const EOF: u8 = 74u8;
+
+/// Pops a descriptor and passes on anything written to it that is still buffered.
+///
+/// FLUSH (75) ( fd -- )
+pub const FLUSH: u8 = 75u8;
+
+/// Pops a whole number and ends the program with it as the exit status. Every call that is
+/// running is unwound, and buffered output is still written.
+///
+/// EXIT (76) ( status -- )
+pub const EXIT: u8 = 76u8;
+
+/// Pops an array of whole numbers from 0 to 255 and pushes them as bytes.
+///
+/// Bytes are a value of their own, which unlike a string need not be valid UTF-8. Byte opcodes
+/// count bytes rather than characters.
+///
+/// BYTES (77) ( arr -- b )
+pub const BYTES: u8 = 77u8;
+
+/// Pops bytes and pushes how many there are.
+///
+/// BYTES_LEN (78) ( b -- len )
+pub const BYTES_LEN: u8 = 78u8;
+
+/// Pops bytes and an index and pushes the byte at that index as a number.
+///
+/// BYTES_GET (79) ( b idx -- n )
+pub const BYTES_GET: u8 = 79u8;
+
+/// Pops bytes and two indices and pushes the bytes from START up to END. Indices past the end
+/// are clamped to the length.
+///
+/// BYTES_SLICE (80) ( b start end -- b[start..end] )
+pub const BYTES_SLICE: u8 = 80u8;
+
+/// Pops two lots of bytes and pushes them joined together.
+///
+/// BYTES_CONCAT (81) ( a b -- a+b )
+pub const BYTES_CONCAT: u8 = 81u8;
+
+/// Pops a string and pushes its UTF-8 encoding as bytes.
+///
+/// ENCODE (82) ( s -- b )
+pub const ENCODE: u8 = 82u8;
+
+/// Pops bytes and pushes them decoded from UTF-8 as a string. If they are not valid UTF-8, MODE
+/// decides between pushing null and raising an encoding error.
+///
+/// DECODE (83) MODE (u8) ( b -- s )
+pub const DECODE: u8 = 83u8;
+
+/// Pops a descriptor and a count and pushes up to COUNT bytes read from it, as they are, or null
+/// at the end of the input. Fewer bytes are pushed when the input ends first.
+///
+/// READ_BYTES (84) ( fd count -- b )
+pub const READ_BYTES: u8 = 84u8;
+
+/// Like [`READ_ALL`], but pushes the bytes as they are.
+///
+/// READ_ALL_BYTES (85) ( fd -- b )
+pub const READ_ALL_BYTES: u8 = 85u8;
+
+/// The length in bytes of the instruction at `pc`, including its operands.
+pub fn instruction_len(bytes: &[u8], pc: usize) -> Option<usize> {
+    let operand_u16 = |offset: usize| -> Option<usize> {
+        Some(u16::from_be_bytes([*bytes.get(pc + offset)?, *bytes.get(pc + offset + 1)?]) as usize)
+    };
+
+    match *bytes.get(pc)? {
+        PUSH => Some(4 + operand_u16(2)?),
+        GET | GET_OPT => Some(3 + operand_u16(1)?),
+        STORE | LOAD | LOAD_OPT | MARKER | GOTO | GOTO_IF | GOTO_IF_NOT | GOTO_IF_NULL | COALESCE => {
+            Some(5)
+        }
+        FORMAT | TONUM | PARSE_INT | OPEN | DECODE => Some(2),
+        _ => Some(1),
+    }
+}
+
+/// How many values an opcode pops and then pushes, as written in its documentation.
+///
+/// This is `None` for opcodes that do not exist, and for those whose effect depends on more
+/// than the opcode: [`CALL`] and [`RET`] depend on the function, [`FORMAT`] on its COUNT and
+/// [`COALESCE`] on whether the value is null.
+pub const fn stack_effect(op: u8) -> Option<(u8, u8)> {
+    let effect = match op {
+        PUSH | LOAD | LOAD_OPT => (0, 1),
+        MARKER | GOTO => (0, 0),
+        PRINT | STORE | POP | GOTO_IF | GOTO_IF_NOT | GOTO_IF_NULL | CLOSE | FLUSH | EXIT => (1, 0),
+        WRITE => (2, 0),
+        SET_DYN => (3, 0),
+        GET | GET_OPT | READLN | TOSTR | NOT | BNOT | SLEN | SUPPER | SLOWER | STRIM | TONUM
+        | TOBOOL | ISNULL | OPEN | READ_ALL | EOF | BYTES | BYTES_LEN | ENCODE | DECODE
+        | READ_ALL_BYTES => (1, 1),
+        DUP => (1, 2),
+        NADD | NSUB | NMUL | NDIV | NMOD | NPOW | SMUL | EQ | NGT | NLT | GET_DYN | GET_DYN_OPT
+        | AND | OR | NEQ | NGE | NLE | SGT | SLT | SGE | SLE | BAND | BOR | BXOR | SHL | SHR
+        | USHR | ROTL | ROTR | SCONCAT | SCHAR_AT | SFIND | SSPLIT | SJOIN | PARSE_INT | READ
+        | BYTES_GET | BYTES_CONCAT | READ_BYTES => (2, 1),
+        SWAP => (2, 2),
+        OVER => (2, 3),
+        SSLICE | BYTES_SLICE => (3, 1),
+        ROT => (3, 3),
+        _ => return None,
+    };
+
+    Some(effect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Documented = (String, u8, Option<(u8, u8)>);
+
+    /// The values on one side of a stack effect. Spaces inside brackets, as in `rotl(a, b)`, do
+    /// not separate values.
+    fn count(side: &str) -> u8 {
+        let mut depth = 0;
+        let mut values = 0;
+        let mut in_value = false;
+
+        for c in side.chars() {
+            match c {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                _ => {}
+            }
+
+            if c.is_whitespace() && depth == 0 {
+                in_value = false;
+            } else if !in_value {
+                in_value = true;
+                values += 1;
+            }
+        }
+
+        values
+    }
+
+    /// The name, number and documented stack effect of every opcode, read from this file.
+    fn documented() -> Vec<Documented> {
+        include_str!("opcodes.rs")
+            .lines()
+            .filter_map(|line| {
+                let line = line.strip_prefix("/// ")?;
+                let (name, rest) = line.split_once(" (")?;
+                let (number, rest) = rest.split_once(')')?;
+
+                if !name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                    return None;
+                }
+
+                let effect = rest.split_once("( ").and_then(|(_, effect)| {
+                    let (effect, _) = effect.split_once(" )")?;
+                    let (pops, pushes) = effect.split_once("--")?;
+
+                    Some((count(pops), count(pushes)))
+                });
+
+                Some((name.to_string(), number.parse().ok()?, effect))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_opcode_documents_its_stack_effect() {
+        let documented = documented();
+
+        assert_eq!(documented.len(), READ_ALL_BYTES as usize + 1);
+
+        for (name, number, effect) in documented {
+            assert!(effect.is_some(), "{name} has no stack effect");
+
+            if let Some(table) = stack_effect(number) {
+                assert_eq!(effect, Some(table), "the stack effect of {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn only_variable_opcodes_are_missing_from_the_table() {
+        let variable = [RET, CALL, FORMAT, COALESCE];
+
+        for op in 0..=READ_ALL_BYTES {
+            assert_eq!(stack_effect(op).is_none(), variable.contains(&op), "opcode {op}");
+        }
+
+        assert_eq!(stack_effect(READ_ALL_BYTES + 1), None);
+    }
+}
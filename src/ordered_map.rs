@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+/// A map from strings that iterates in insertion order, so objects built the same way always
+/// serialize to the same bytes.
+#[derive(Debug, Clone, Default)]
+pub struct OrderedMap<V> {
+    indices: HashMap<String, usize>,
+    entries: Vec<(String, V)>,
+}
+
+impl<V> OrderedMap<V> {
+    pub fn new() -> Self {
+        OrderedMap {
+            indices: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.indices.get(key).map(|idx| &self.entries[*idx].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.indices.get(key).map(|idx| &mut self.entries[*idx].1)
+    }
+
+    /// Inserts `value` at `key`. Replacing an existing entry keeps its position.
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        if let Some(idx) = self.indices.get(&key) {
+            Some(std::mem::replace(&mut self.entries[*idx].1, value))
+        } else {
+            self.indices.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+}
+
+impl<V, const N: usize> From<[(String, V); N]> for OrderedMap<V> {
+    fn from(entries: [(String, V); N]) -> Self {
+        let mut map = OrderedMap::new();
+
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+
+        map
+    }
+}
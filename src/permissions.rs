@@ -0,0 +1,200 @@
+use crate::fs;
+use std::fmt::{Display, Formatter};
+use std::path::{absolute, Path, PathBuf};
+
+/// Paths a script may use. A path grants itself and everything beneath it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathGrant {
+    All,
+    Only(Vec<PathBuf>),
+}
+
+impl PathGrant {
+    /// No paths at all.
+    pub fn none() -> Self {
+        PathGrant::Only(Vec::new())
+    }
+
+    /// Grants `path` as well as whatever is already granted.
+    pub fn allow(&mut self, path: impl AsRef<Path>) {
+        if let PathGrant::Only(paths) = self {
+            paths.push(normalize(path.as_ref()));
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        match self {
+            PathGrant::All => true,
+            PathGrant::Only(paths) => paths.iter().any(|granted| path.starts_with(granted)),
+        }
+    }
+}
+
+/// What a script may do outside the VM. Anything not granted fails with a `NotAllowed` error.
+///
+/// The default allows the standard streams, and nothing else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: PathGrant,
+    pub write: PathGrant,
+    pub stdin: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+    pub env: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions {
+            read: PathGrant::none(),
+            write: PathGrant::none(),
+            stdin: true,
+            stdout: true,
+            stderr: true,
+            env: false,
+        }
+    }
+}
+
+impl Permissions {
+    /// Allows everything.
+    pub fn all() -> Self {
+        Permissions {
+            read: PathGrant::All,
+            write: PathGrant::All,
+            stdin: true,
+            stdout: true,
+            stderr: true,
+            env: true,
+        }
+    }
+
+    /// Allows nothing, not even the standard streams.
+    pub fn none() -> Self {
+        Permissions {
+            stdin: false,
+            stdout: false,
+            stderr: false,
+            ..Permissions::default()
+        }
+    }
+
+    /// These permissions with every granted path passed through `resolve`, so they can be compared
+    /// with paths resolved the same way.
+    pub(crate) fn resolved(&self, mut resolve: impl FnMut(&Path) -> PathBuf) -> Permissions {
+        let mut grant = |grant: &PathGrant| match grant {
+            PathGrant::All => PathGrant::All,
+            PathGrant::Only(paths) => {
+                PathGrant::Only(paths.iter().map(|path| resolve(path)).collect())
+            }
+        };
+
+        Permissions {
+            read: grant(&self.read),
+            write: grant(&self.write),
+            ..self.clone()
+        }
+    }
+
+    pub fn allows(&self, access: &Access) -> bool {
+        match access {
+            Access::Read(path) => self.read.contains(path),
+            Access::Write(path) => self.write.contains(path),
+            Access::Stdin => self.stdin,
+            Access::Stdout => self.stdout,
+            Access::Stderr => self.stderr,
+            Access::Env(_) => self.env,
+        }
+    }
+}
+
+/// Something a script asked to do outside the VM. Paths are absolute, with `.` and `..`
+/// resolved. The VM also resolves symlinks through its I/O backend before checking them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read(PathBuf),
+    Write(PathBuf),
+    Stdin,
+    Stdout,
+    Stderr,
+    /// Reading the environment variable with this name.
+    Env(String),
+}
+
+impl Access {
+    pub fn read(path: &Path) -> Self {
+        Access::Read(normalize(path))
+    }
+
+    pub fn write(path: &Path) -> Self {
+        Access::Write(normalize(path))
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read(path) => write!(f, "reading {}", path.display()),
+            Access::Write(path) => write!(f, "writing {}", path.display()),
+            Access::Stdin => write!(f, "reading stdin"),
+            Access::Stdout => write!(f, "writing stdout"),
+            Access::Stderr => write!(f, "writing stderr"),
+            Access::Env(name) => write!(f, "reading the environment variable {name}"),
+        }
+    }
+}
+
+/// Makes `path` absolute and resolves `.` and `..` without touching the filesystem. The VM
+/// resolves symlinks through its I/O backend before it checks a path.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    fs::normalize(&absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(paths: &[&str]) -> PathGrant {
+        let mut grant = PathGrant::none();
+
+        for path in paths {
+            grant.allow(path);
+        }
+
+        grant
+    }
+
+    fn allows(grant: &PathGrant, path: &str) -> bool {
+        grant.contains(&normalize(Path::new(path)))
+    }
+
+    #[test]
+    fn a_path_grants_what_is_beneath_it() {
+        let grant = grant(&["/teavm-missing/data"]);
+
+        assert!(allows(&grant, "/teavm-missing/data"));
+        assert!(allows(&grant, "/teavm-missing/data/a/b.txt"));
+        assert!(!allows(&grant, "/teavm-missing/database"));
+        assert!(!allows(&grant, "/teavm-missing"));
+        assert!(!allows(&PathGrant::none(), "/teavm-missing/data"));
+        assert!(allows(&PathGrant::All, "/teavm-missing"));
+    }
+
+    #[test]
+    fn dots_are_resolved_before_checking() {
+        let grant = grant(&["/teavm-missing/data"]);
+
+        assert!(allows(&grant, "/teavm-missing/data/./a/../b"));
+        assert!(!allows(&grant, "/teavm-missing/data/../secret"));
+        assert!(!allows(&grant, "/teavm-missing/data/a/../../secret"));
+        assert_eq!(normalize(Path::new("/../teavm-missing/./a/..")), Path::new("/teavm-missing"));
+    }
+
+    #[test]
+    fn relative_paths_are_checked_from_the_current_directory() {
+        let cwd = std::env::current_dir().unwrap();
+
+        assert_eq!(normalize(Path::new("teavm-missing/../x")), cwd.join("x"));
+        assert_eq!(normalize(Path::new("..")), cwd.parent().unwrap_or(&cwd));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::errors::VmError;
+use crate::scope::RANDOM_VAR;
+use crate::values::{SpecificValue, TeaNull, TeaNumber};
+use crate::vm::Vm;
+
+/// The seed of every new VM, so that a program gives the same results each time it runs until
+/// something seeds it.
+pub(crate) const DEFAULT_SEED: u64 = 0x7EA5_EED5;
+
+/// A SplitMix64 generator. It is fast and spreads its output well, which is all simulations
+/// need, but it is easy to predict, so it must not be used for anything secret.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to but not including 1, with 53 random bits.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from 0 up to but not including `bound`, which must not be 0. Outputs that would
+    /// favour small numbers are thrown away, so every number is equally likely.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        let threshold = bound.wrapping_neg() % bound;
+
+        loop {
+            let x = self.next_u64();
+
+            if x >= threshold {
+                return x % bound;
+            }
+        }
+    }
+}
+
+/// Fills in the `random` object of the global scope.
+///
+/// - `float()` pushes a number from 0 up to but not including 1.
+/// - `int(min, max)` pushes a whole number from `min` up to but not including `max`.
+/// - `choice(arr)` pushes an item of a non-empty array.
+/// - `shuffle(arr)` puts the items of an array in a random order, in place.
+/// - `seed(n)` restarts the sequence from the whole number `n`.
+///
+/// Every VM starts from the same seed, so a program is reproducible unless it, or the embedder
+/// through [`Vm::seed_random`], seeds it otherwise.
+pub(crate) fn register(vm: &mut Vm) -> Result<(), VmError> {
+    vm.register_native_in(RANDOM_VAR, "float", 0, |vm, _args| {
+        Ok(TeaNumber(vm.random.next_f64()).to_value())
+    })?;
+
+    vm.register_native_in(RANDOM_VAR, "int", 2, |vm, args| {
+        let min = args[0].as_tea_integer()?;
+        let max = args[1].as_tea_integer()?;
+
+        if min >= max {
+            return Err(VmError::Native(format!("random.int: {min} to {max} is not a range")));
+        }
+
+        let offset = vm.random.below(max.wrapping_sub(min) as u64);
+
+        Ok(TeaNumber(min.wrapping_add(offset as i64) as f64).to_value())
+    })?;
+
+    vm.register_native_in(RANDOM_VAR, "choice", 1, |vm, args| {
+        let len = vm.heap.array(&args[0])?.items.len();
+
+        if len == 0 {
+            return Err(VmError::Native("random.choice: the array is empty".to_string()));
+        }
+
+        let idx = vm.random.below(len as u64) as usize;
+
+        Ok(vm.heap.array(&args[0])?.items[idx].clone())
+    })?;
+
+    vm.register_native_in(RANDOM_VAR, "shuffle", 1, |vm, args| {
+        let (heap, random) = (&mut vm.heap, &mut vm.random);
+        let items = &mut heap.array_mut(&args[0])?.items;
+
+        for i in (1..items.len()).rev() {
+            items.swap(i, random.below(i as u64 + 1) as usize);
+        }
+
+        Ok(TeaNull.to_value())
+    })?;
+
+    vm.register_native_in(RANDOM_VAR, "seed", 1, |vm, args| {
+        vm.random = Rng::new(args[0].as_tea_integer()? as u64);
+
+        Ok(TeaNull.to_value())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{FromTea, IntoTea};
+    use crate::errors::RuntimeError;
+    use crate::values::Value;
+
+    /// Calls `random.name` with `args` and returns its result.
+    fn call(vm: &mut Vm, name: &str, args: &[Value]) -> Value {
+        let random = vm.get_global(RANDOM_VAR).unwrap();
+        let (native, _) = vm.heap().object(&random).unwrap().entries.get(name).unwrap().clone();
+
+        vm.call(&native, args).unwrap_or_else(|e| e.raise()).remove(0)
+    }
+
+    fn float(vm: &mut Vm) -> f64 {
+        let value = call(vm, "float", &[]);
+
+        f64::from_tea(vm.heap(), &value).unwrap()
+    }
+
+    fn int(vm: &mut Vm, min: f64, max: f64) -> f64 {
+        let args = [TeaNumber(min).to_value(), TeaNumber(max).to_value()];
+
+        let value = call(vm, "int", &args);
+
+        f64::from_tea(vm.heap(), &value).unwrap()
+    }
+
+    fn sequence(vm: &mut Vm) -> Vec<f64> {
+        (0..8).flat_map(|_| [float(vm), int(vm, -5., 5.)]).collect()
+    }
+
+    #[test]
+    fn new_vms_give_the_same_sequence() {
+        assert_eq!(sequence(&mut Vm::new()), sequence(&mut Vm::new()));
+    }
+
+    #[test]
+    fn seeding_restarts_the_sequence() {
+        let mut vm = Vm::new();
+        let seed = [TeaNumber(42.).to_value()];
+
+        call(&mut vm, "seed", &seed);
+        let first = sequence(&mut vm);
+        call(&mut vm, "seed", &seed);
+
+        assert_eq!(sequence(&mut vm), first);
+
+        vm.seed_random(DEFAULT_SEED);
+
+        assert_eq!(sequence(&mut vm), sequence(&mut Vm::new()));
+    }
+
+    #[test]
+    fn floats_and_ints_stay_in_their_ranges() {
+        let mut vm = Vm::new();
+
+        for _ in 0..1000 {
+            assert!((0. ..1.).contains(&float(&mut vm)));
+
+            let n = int(&mut vm, -3., 4.);
+
+            assert!((-3. ..4.).contains(&n) && n.fract() == 0., "{n}");
+        }
+
+        assert_eq!(int(&mut vm, 7., 8.), 7.);
+    }
+
+    #[test]
+    fn shuffle_keeps_the_same_items() {
+        let mut vm = Vm::new();
+        let items: Vec<f64> = (0..20).map(f64::from).collect();
+        let array = [items.clone().into_tea(vm.heap_mut())];
+
+        vm.set_global(100, &array[0]);
+        call(&mut vm, "shuffle", &array);
+
+        let mut shuffled = Vec::<f64>::from_tea(vm.heap(), &array[0]).unwrap();
+
+        assert_ne!(shuffled, items);
+
+        shuffled.sort_by(f64::total_cmp);
+
+        assert_eq!(shuffled, items);
+    }
+}
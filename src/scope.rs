@@ -1,17 +1,33 @@
-use crate::errors::{DeallocatedError, FileError, NoValueError, RuntimeError};
-use crate::values::{SpecificValue, TeaNumber, TeaObject, Value};
+use crate::errors::{DeallocatedError, RuntimeError, StackUnderflow};
+use crate::heap::Heap;
+use crate::ordered_map::OrderedMap;
+use crate::values::{TeaArray, TeaNumber, TeaObject, Value};
 use std::collections::{HashMap, VecDeque};
-use std::f64::consts::{PI, TAU};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::f64::consts::{E, PI, TAU};
+
+/// The global variable holding the `io` object.
+pub const IO_VAR: u32 = 0;
+/// The global variable holding the `math` object.
+pub const MATH_VAR: u32 = 1;
+/// The global variable holding the `host` object, where the embedder registers native functions.
+pub const HOST_VAR: u32 = 2;
+/// The global variable holding the `args` array, the arguments the program was started with.
+pub const ARGS_VAR: u32 = 3;
+/// The global variable holding the `env` object, whose `get` function reads environment
+/// variables.
+pub const ENV_VAR: u32 = 4;
+/// The global variable holding the `fs` object, whose functions work with paths, directories
+/// and file metadata.
+pub const FS_VAR: u32 = 5;
+/// The global variable holding the `random` object, whose functions draw from the seedable
+/// random number generator of the VM.
+pub const RANDOM_VAR: u32 = 6;
 
 #[derive(Clone)]
 pub struct Scope {
     stack: VecDeque<Value>,
     vars: HashMap<u32, Value>,
-    parent: Option<*mut Scope>,
-    file_descriptors: HashMap<u32, PathBuf>
+    parent: Option<*mut Scope>
 }
 
 impl Scope {
@@ -19,31 +35,38 @@ impl Scope {
         Scope {
             stack: VecDeque::new(),
             vars: HashMap::new(),
-            parent,
-            file_descriptors: HashMap::new()
+            parent
         }
     }
 
-    pub(crate) fn new_global() -> Self {
+    pub(crate) fn new_global(heap: &mut Heap) -> Self {
         let mut s = Scope {
             stack: VecDeque::new(),
             vars: HashMap::new(),
-            parent: None,
-            file_descriptors: HashMap::new()
+            parent: None
         };
 
-        let math: HashMap<String, (Value, u8)> = HashMap::from([
+        let math: OrderedMap<(Value, u8)> = OrderedMap::from([
             ("pi".into(), (Value::from_specific(&TeaNumber(PI)), 2u8)),
             ("tau".into(), (Value::from_specific(&TeaNumber(TAU)), 2u8)),
+            ("e".into(), (Value::from_specific(&TeaNumber(E)), 2u8)),
+            ("inf".into(), (Value::from_specific(&TeaNumber(f64::INFINITY)), 2u8)),
+            ("nan".into(), (Value::from_specific(&TeaNumber(f64::NAN)), 2u8)),
         ]);
 
-        let io: HashMap<String, (Value, u8)> = HashMap::from([
+        let io: OrderedMap<(Value, u8)> = OrderedMap::from([
             ("stdout".into(), (Value::from_specific(&TeaNumber(0.)), 2u8)),
             ("stdin".into(), (Value::from_specific(&TeaNumber(1.)), 2u8)),
+            ("stderr".into(), (Value::from_specific(&TeaNumber(2.)), 2u8)),
         ]);
         
-        s.set_var(&0, &TeaObject::new(io).to_value());
-        s.set_var(&1, &TeaObject::new(math).to_value());
+        s.set_var(&IO_VAR, &heap.alloc_object(TeaObject::new(io)));
+        s.set_var(&MATH_VAR, &heap.alloc_object(TeaObject::new(math)));
+        s.set_var(&HOST_VAR, &heap.alloc_object(TeaObject::new(OrderedMap::new())));
+        s.set_var(&ARGS_VAR, &heap.alloc_array(TeaArray::new(Vec::new())));
+        s.set_var(&ENV_VAR, &heap.alloc_object(TeaObject::new(OrderedMap::new())));
+        s.set_var(&FS_VAR, &heap.alloc_object(TeaObject::new(OrderedMap::new())));
+        s.set_var(&RANDOM_VAR, &heap.alloc_object(TeaObject::new(OrderedMap::new())));
 
         s
     }
@@ -56,49 +79,46 @@ impl Scope {
         self.stack.push_back(value.clone())
     }
 
-    pub fn get_var(&self, idx: &u32) -> Option<Value> {
-        if let Some(var) = self.vars.get(&idx) {
-            Option::from(var.clone())
-        } else {
-            unsafe { self.parent?.as_ref() }?.get_var(idx)
-        }
+    /// The top `count` values of the stack, in the order they were pushed.
+    pub fn top(&self, count: usize) -> Vec<Value> {
+        self.stack.range(self.stack.len() - count..).cloned().collect()
     }
-    
-    pub fn add_fd(&mut self, fd: &u32, path: &PathBuf) {
-        self.file_descriptors.insert(fd.clone(), path.clone());
+
+    /// Empties the stack, returning its values in the order they were pushed.
+    pub(crate) fn take_stack(&mut self) -> Vec<Value> {
+        self.stack.drain(..).collect()
     }
-    
-    pub fn read_fd(&self, fd: &u32) -> Result<&[u8], FileError> {
-        if let Some(path) = self.file_descriptors.get(fd) {
-            if let Ok(mut file) = File::create_new(path) {
-                let vec: *mut Vec<u8> = &mut vec![];
-                
-                if let Ok(_) = file.read_to_end(unsafe { vec.as_mut() }.unwrap()) {
-                    Ok(unsafe { vec.as_ref() }.unwrap().as_slice())
-                } else {
-                    Err(FileError(Some(path.clone()), "could not read file".to_string()))
-                }
-            } else {
-                Err(FileError(Some(path.clone()), "could not open file".to_string()))
-            }
+
+    /// Checks that the stack holds at least `count` values for `op`.
+    pub fn require(&self, op: &str, count: usize) -> Result<(), StackUnderflow> {
+        if self.stack.len() < count {
+            Err(StackUnderflow(op.to_string(), count))
         } else {
-            Err(FileError(None, format!("could not find file at fd {fd}")))
+            Ok(())
         }
     }
 
-    pub fn write_fd(&self, fd: &u32, content: &[u8]) -> Result<(), FileError> {
-        if let Some(path) = self.file_descriptors.get(fd) {
-            if let Ok(mut file) = File::create_new(path) {
-                file.write_all(content).map_err(|_| FileError(Some(path.clone()), "could not write to file".to_string()))
-            } else {
-                Err(FileError(Some(path.clone()), "could not open file".to_string()))
-            }
-        } else {
-            Err(FileError(None, format!("could not find file at fd {fd}")))
+    /// The values on the stack and in the variables of this scope and every parent scope,
+    /// which is each active call frame.
+    pub(crate) fn roots(&self) -> Vec<&Value> {
+        let mut roots: Vec<&Value> = self.stack.iter().chain(self.vars.values()).collect();
+
+        if let Some(parent) = self.parent.and_then(|parent| unsafe { parent.as_ref() }) {
+            roots.extend(parent.roots());
         }
+
+        roots
     }
 
-    pub fn set_var(&mut self, idx: &u32, value: &Value) -> () {
+    pub fn get_var(&self, idx: &u32) -> Option<Value> {
+        if let Some(var) = self.vars.get(idx) {
+            Option::from(var.clone())
+        } else {
+            unsafe { self.parent?.as_ref() }?.get_var(idx)
+        }
+    }
+    
+    pub fn set_var(&mut self, idx: &u32, value: &Value) {
         self.vars.insert(*idx, value.clone());
 
         if let Some(parent) = self.parent {
@@ -0,0 +1,118 @@
+//! Builders for the bytecode that tests run.
+
+use crate::backend::MemoryIo;
+use crate::errors::{RuntimeError, VmError};
+use crate::opcodes;
+use crate::permissions::Permissions;
+use crate::values::{SpecificValue, Value, ValueKind};
+use crate::vm::Vm;
+
+/// A value as it is encoded after PUSH.
+pub(crate) fn value(kind: ValueKind, data: &[u8]) -> Vec<u8> {
+    [&[kind as u8][..], &(data.len() as u16).to_be_bytes(), data].concat()
+}
+
+pub(crate) fn num(n: f64) -> Vec<u8> {
+    value(ValueKind::Number, &n.to_be_bytes())
+}
+
+pub(crate) fn str(s: &str) -> Vec<u8> {
+    value(ValueKind::Str, s.as_bytes())
+}
+
+pub(crate) fn bool(b: bool) -> Vec<u8> {
+    value(ValueKind::Bool, &[b as u8])
+}
+
+pub(crate) fn null() -> Vec<u8> {
+    value(ValueKind::Null, &[])
+}
+
+pub(crate) fn array(items: &[Vec<u8>]) -> Vec<u8> {
+    value(ValueKind::Array, &[(items.len() as u16).to_be_bytes().to_vec(), items.concat()].concat())
+}
+
+/// An object literal whose entries are all writable.
+pub(crate) fn object(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut data = (entries.len() as u16).to_be_bytes().to_vec();
+
+    for (key, value) in entries {
+        data.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        data.extend_from_slice(key.as_bytes());
+        data.extend_from_slice(value);
+        data.push(0);
+    }
+
+    value(ValueKind::Object, &data)
+}
+
+pub(crate) fn function(code: &[u8]) -> Vec<u8> {
+    value(ValueKind::Function, &[&(code.len() as u16).to_be_bytes()[..], code].concat())
+}
+
+pub(crate) fn push(value: Vec<u8>) -> Vec<u8> {
+    [vec![opcodes::PUSH], value].concat()
+}
+
+pub(crate) fn store(idx: u32) -> Vec<u8> {
+    [&[opcodes::STORE][..], &idx.to_be_bytes()].concat()
+}
+
+pub(crate) fn load(idx: u32) -> Vec<u8> {
+    [&[opcodes::LOAD][..], &idx.to_be_bytes()].concat()
+}
+
+pub(crate) fn get(key: &str) -> Vec<u8> {
+    [&[opcodes::GET][..], &(key.len() as u16).to_be_bytes(), key.as_bytes()].concat()
+}
+
+/// MARKER, or one of the GOTO family, with the marker IDX.
+pub(crate) fn jump(op: u8, idx: u32) -> Vec<u8> {
+    [&[op][..], &idx.to_be_bytes()].concat()
+}
+
+/// A VM that may do anything, through `io`.
+pub(crate) fn vm(io: &MemoryIo) -> Vm {
+    let mut vm = Vm::new();
+
+    vm.set_io_backend(io.clone()).unwrap_or_else(|e| e.raise());
+    vm.set_permissions(Permissions::all());
+    vm
+}
+
+/// Runs `code` in [`vm`] and returns how it ended. The VM is dropped before returning, so
+/// buffered output has reached `io`.
+pub(crate) fn run(io: &MemoryIo, code: &[u8]) -> Result<Option<i32>, VmError> {
+    let mut vm = vm(io);
+
+    vm.run(code)?;
+
+    Ok(vm.exit_status())
+}
+
+/// What `code` printed to stdout, which must be UTF-8.
+pub(crate) fn stdout(io: &MemoryIo, code: &[u8]) -> String {
+    run(io, code).unwrap_or_else(|e| e.raise());
+
+    String::from_utf8(io.stdout()).expect("stdout is not UTF-8")
+}
+
+/// The variable that [`eval`] leaves its result in.
+const RESULT: u32 = 100;
+
+/// Runs `code` with no I/O and returns the value it left on top of the stack.
+pub(crate) fn eval(code: &[u8]) -> Result<Value, VmError> {
+    let mut vm = vm(&MemoryIo::new());
+
+    vm.run(&[code, &store(RESULT)].concat())?;
+
+    Ok(vm.get_global(RESULT).expect("the result was not stored"))
+}
+
+pub(crate) fn number(code: &[u8]) -> Result<f64, VmError> {
+    Ok(eval(code)?.as_tea_number()?.value())
+}
+
+pub(crate) fn string(code: &[u8]) -> Result<String, VmError> {
+    Ok(eval(code)?.as_tea_string()?.value())
+}
@@ -1,60 +1,155 @@
-use crate::errors::CannotConstruct;
-use core::slice::SlicePattern;
-use std::collections::HashMap;
+use crate::errors::{CannotConstruct, NotAllowed, RuntimeError, VmError};
+use crate::ordered_map::OrderedMap;
 use std::fmt::Debug;
 use std::ops::{Add, Div, Mul, Rem, Sub};
 
 static TRUE: &[u8] = [1u8].as_slice();
 static FALSE: &[u8] = [0u8].as_slice();
 
+/// Entry flag marking an object entry that cannot be overwritten.
+pub const READONLY: u8 = 2u8;
+
+/// The kind of data held by a [`Value`], encoded as the first byte of a value in bytecode.
+///
+/// Objects and arrays live on the [`Heap`](crate::heap::Heap); at runtime their values hold a
+/// u32 handle rather than the entries themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueKind {
+    Number = 0u8,
+    Str = 1u8,
+    Bool = 2u8,
+    Function = 3u8,
+    Object = 4u8,
+    Array = 5u8,
+    Null = 6u8,
+    Native = 7u8,
+    Bytes = 8u8,
+}
+
+impl ValueKind {
+    pub(crate) fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ValueKind::Number),
+            1 => Some(ValueKind::Str),
+            2 => Some(ValueKind::Bool),
+            3 => Some(ValueKind::Function),
+            4 => Some(ValueKind::Object),
+            5 => Some(ValueKind::Array),
+            6 => Some(ValueKind::Null),
+            7 => Some(ValueKind::Native),
+            8 => Some(ValueKind::Bytes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_heap(&self) -> bool {
+        matches!(self, ValueKind::Object | ValueKind::Array)
+    }
+}
+
+/// A value, either read from bytecode or made while running it.
+///
+/// Bytecode gives the length of a value in a u16, but values made while running, such as a
+/// whole file from READ_ALL, can be longer. Such values cannot be written back into bytecode.
 #[derive(Debug, Clone)]
 pub struct Value {
-    pub data_len: u16,
+    pub kind: ValueKind,
+    pub data_len: usize,
     pub data: Box<[u8]>,
 }
 
 impl Value {
-    fn new(data: Box<[u8]>) -> Self {
+    fn new(kind: ValueKind, data: Box<[u8]>) -> Self {
         Value {
-            data_len: data.len() as u16,
+            kind,
+            data_len: data.len(),
             data,
         }
     }
 
     fn new_again(data: [u8; 8]) -> Self {
         Value {
-            data_len: data.len() as u16,
+            kind: ValueKind::Number,
+            data_len: data.len(),
             data: Box::from(data),
         }
     }
 
-    pub(crate) fn from_specific<'a, T>(specific: &'a dyn SpecificValue<Value = T>) -> Self {
+    pub(crate) fn from_specific<T>(specific: &dyn SpecificValue<Value = T>) -> Self {
         specific.to_value()
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = self.data_len.to_be_bytes().to_vec();
+    pub(crate) fn from_handle(kind: ValueKind, handle: u32) -> Self {
+        Value::new(kind, Box::from(handle.to_be_bytes()))
+    }
+
+    /// A native function value holds the index of the function in the VM, followed by its name.
+    pub(crate) fn from_native(idx: u32, name: &str) -> Self {
+        Value::new(ValueKind::Native, Box::from([&idx.to_be_bytes(), name.as_bytes()].concat()))
+    }
 
-        for byte in &self.data {
-            bytes.push(*byte)
+    pub(crate) fn native_index(&self) -> Option<u32> {
+        if self.kind == ValueKind::Native {
+            Some(u32::from_be_bytes(self.data.get(0..4)?.try_into().ok()?))
+        } else {
+            None
         }
+    }
 
-        bytes
+    pub(crate) fn native_name(&self) -> Option<&str> {
+        std::str::from_utf8(self.data.get(4..)?).ok()
+    }
+
+    /// The heap handle of an object or array value.
+    pub(crate) fn handle(&self) -> Option<u32> {
+        if self.kind.is_heap() {
+            Some(u32::from_be_bytes((*self.data).try_into().ok()?))
+        } else {
+            None
+        }
     }
 
-    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
-        let len = u16::from_be_bytes(
-            bytes[0..=1]
-                .try_into()
-                .expect("Failed to convert bytes into u16."),
-        );
+    /// Encodes the value as it appears in bytecode, which fails if it is too long.
+    fn to_bytes(&self) -> Result<Vec<u8>, NotAllowed> {
+        let len = u16::try_from(self.data_len).map_err(|_| too_long("values", self.data_len))?;
+        let mut bytes: Vec<u8> = vec![self.kind as u8];
 
-        let data = &bytes[2usize..(len as usize) + 2];
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
 
-        Value::new(Box::try_from(data.to_vec()).unwrap())
+        Ok(bytes)
     }
 
-    pub(crate) fn as_tea_number(&self) -> Result<TeaNumber, CannotConstruct> {
+    /// Decodes a value as it appears in bytecode, failing if its kind is unknown or the bytes end
+    /// before it does.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, VmError> {
+        let malformed = |item: &str| {
+            let header = &bytes[..bytes.len().min(3)];
+
+            VmError::CannotConstruct(format!("values::Value::{item}"), format!("{header:?}"))
+        };
+
+        let kind = bytes
+            .first()
+            .and_then(|byte| ValueKind::from_u8(*byte))
+            .ok_or_else(|| malformed("kind"))?;
+
+        let len = bytes
+            .get(1..3)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .ok_or_else(|| malformed("data_len"))?;
+
+        let data = bytes.get(3..len + 3).ok_or_else(|| malformed("data"))?;
+
+        Ok(Value::new(kind, Box::from(data)))
+    }
+
+    pub fn as_tea_number(&self) -> Result<TeaNumber, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Number {
+            return Err(CannotConstruct("values::TeaNumber::kind".to_string(), self));
+        }
+
         if let Ok(bytes) = (*self.data).try_into() {
             let value = f64::from_be_bytes(bytes);
 
@@ -64,7 +159,42 @@ impl Value {
         }
     }
 
-    pub(crate) fn as_tea_string(&self) -> Result<TeaStr, CannotConstruct> {
+    /// Reads a number as an i64, for opcodes and natives that take whole numbers such as counts,
+    /// bytes and seeds. The number must be whole and within the range of an i64.
+    pub fn as_tea_integer(&self) -> Result<i64, CannotConstruct<'_>> {
+        let value = self.as_tea_number()?.value();
+
+        if value.fract() == 0. && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+            Ok(value as i64)
+        } else {
+            Err(CannotConstruct("values::TeaNumber::integer".to_string(), self))
+        }
+    }
+
+    /// Reads a whole number as a 32-bit two's complement integer, wrapping numbers outside the
+    /// range of an i32 as JavaScript does.
+    pub(crate) fn as_tea_int32(&self) -> Result<i32, CannotConstruct<'_>> {
+        match self.as_tea_number()?.value() {
+            value if value.is_finite() && value.fract() == 0. => {
+                Ok(value.rem_euclid(4_294_967_296.) as u32 as i32)
+            }
+            _ => Err(CannotConstruct("values::TeaNumber::int32".to_string(), self)),
+        }
+    }
+
+    /// Reads a number as an index, which must be whole and not negative.
+    pub fn as_tea_index(&self) -> Result<usize, CannotConstruct<'_>> {
+        match self.as_tea_integer()? {
+            idx if idx >= 0 => Ok(idx as usize),
+            _ => Err(CannotConstruct("values::TeaNumber::index".to_string(), self)),
+        }
+    }
+
+    pub fn as_tea_string(&self) -> Result<TeaStr, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Str {
+            return Err(CannotConstruct("values::TeaStr::kind".to_string(), self));
+        }
+
         if let Ok(value) = String::from_utf8(self.data.to_vec()) {
             Ok(TeaStr(value))
         } else {
@@ -72,49 +202,126 @@ impl Value {
         }
     }
 
-    pub(crate) fn as_tea_bool(&self) -> TeaBool {
-        let value = self.data[0] == 1u8;
+    /// Converts a value to a number. Strings are parsed after trimming whitespace, and booleans
+    /// become 1 or 0.
+    pub fn coerce_number(&self) -> Result<TeaNumber, CannotConstruct<'_>> {
+        match self.kind {
+            ValueKind::Number => self.as_tea_number(),
+            ValueKind::Bool => Ok(TeaNumber(if self.as_tea_bool()?.value() { 1. } else { 0. })),
+            ValueKind::Str => self
+                .as_tea_string()?
+                .0
+                .trim()
+                .parse()
+                .map(TeaNumber)
+                .map_err(|_| CannotConstruct("values::TeaNumber::parse".to_string(), self)),
+            _ => Err(CannotConstruct("values::TeaNumber::convert".to_string(), self)),
+        }
+    }
+
+    /// Parses a string as a whole number in `radix`, which must be between 2 and 36. Whitespace
+    /// around the number and a leading sign are allowed.
+    pub fn parse_int(&self, radix: f64) -> Result<TeaNumber, CannotConstruct<'_>> {
+        let str = self.as_tea_string()?;
+
+        if radix.fract() != 0. || !(2. ..=36.).contains(&radix) {
+            return Err(CannotConstruct("values::TeaNumber::radix".to_string(), self));
+        }
+
+        i64::from_str_radix(str.0.trim(), radix as u32)
+            .map(|value| TeaNumber(value as f64))
+            .map_err(|_| CannotConstruct("values::TeaNumber::parse".to_string(), self))
+    }
+
+    /// Whether a value counts as true: false, null, zero, NaN and the empty string do not, and
+    /// every other value does.
+    pub fn is_truthy(&self) -> bool {
+        match self.kind {
+            ValueKind::Bool => self.as_tea_bool().is_ok_and(|b| b.value()),
+            ValueKind::Null => false,
+            ValueKind::Number => self.as_tea_number().is_ok_and(|n| n.0 != 0. && !n.0.is_nan()),
+            ValueKind::Str | ValueKind::Bytes => !self.data.is_empty(),
+            _ => true,
+        }
+    }
+
+    pub fn as_tea_bytes(&self) -> Result<TeaBytes, CannotConstruct<'_>> {
+        if self.kind == ValueKind::Bytes {
+            Ok(TeaBytes(self.data.to_vec()))
+        } else {
+            Err(CannotConstruct("values::TeaBytes::kind".to_string(), self))
+        }
+    }
+
+    pub fn as_tea_bool(&self) -> Result<TeaBool, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Bool {
+            return Err(CannotConstruct("values::TeaBool::kind".to_string(), self));
+        }
 
-        TeaBool(value)
+        if let Some(byte) = self.data.first() {
+            Ok(TeaBool(*byte == 1u8))
+        } else {
+            Err(CannotConstruct("values::TeaBool::value".to_string(), self))
+        }
     }
 
-    pub(crate) fn as_tea_null(&self) -> TeaNull {
-        TeaNull
+    pub fn as_tea_null(&self) -> Result<TeaNull, CannotConstruct<'_>> {
+        if self.kind == ValueKind::Null {
+            Ok(TeaNull)
+        } else {
+            Err(CannotConstruct("values::TeaNull::kind".to_string(), self))
+        }
     }
 
-    pub(crate) fn as_tea_function(&self) -> Result<TeaFunction, CannotConstruct> {
-        if let Ok(bytes) = self.data[1..=2].try_into() {
+    pub fn as_tea_function(&self) -> Result<TeaFunction, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Function {
+            return Err(CannotConstruct("values::TeaFunction::kind".to_string(), self));
+        }
+
+        if let Some(Ok(bytes)) = self.data.get(0..=1).map(|v| v.try_into()) {
             let code_len = u16::from_be_bytes(bytes);
-            let code = &self.data[3..=(2 + code_len) as usize];
 
-            Ok(TeaFunction::new(code_len, code))
+            if let Some(code) = self.data.get(2..2 + code_len as usize) {
+                Ok(TeaFunction::new(code_len, code))
+            } else {
+                Err(CannotConstruct("values::TeaFunction::code".to_string(), self))
+            }
         } else {
             Err(CannotConstruct("values::TeaFunction::code_len".to_string(), self))
         }
     }
 
-    pub(crate) fn as_tea_object(&self) -> Result<TeaObject, CannotConstruct> {
-        if let Ok(bytes) = self.data[0..=1].try_into() {
+    /// Decodes an object literal. Objects on the heap are reached through
+    /// [`Heap::object`](crate::heap::Heap::object) instead.
+    pub(crate) fn as_tea_object(&self) -> Result<TeaObject, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Object {
+            return Err(CannotConstruct("values::TeaObject::kind".to_string(), self));
+        }
+
+        if let Some(Ok(bytes)) = self.data.get(0..=1).map(|v| v.try_into()) {
             let num_entries = u16::from_be_bytes(bytes);
 
-            let mut entries: HashMap<String, (Value, u8)> = HashMap::new();
+            let mut entries: OrderedMap<(Value, u8)> = OrderedMap::new();
             let mut offset = 2usize;
 
-            loop {
-                if entries.len() as u16 >= num_entries {
-                    break;
-                }
+            for _ in 0..num_entries {
 
-                if let Some(key_len) = self.data.get(offset + 1).map(|v| *v as usize) {
+                if let Some(key_len) = self
+                    .data
+                    .get(offset..offset + 2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]) as usize)
+                {
                     offset += 2;
 
-                    if let Ok(key) = String::from_utf8(self.data[offset..=offset + key_len - 1].to_vec()) {
+                    if let Some(Ok(key)) = self
+                        .data
+                        .get(offset..offset + key_len)
+                        .map(|v| String::from_utf8(v.to_vec()))
+                    {
                         offset += key_len;
                         
-                        if let Some(value_bytes) = self.data.split_at_checked(offset) {
-                            let value = Value::from_bytes(value_bytes.1);
-
-                            offset += (value.data_len as usize) + 2;
+                        if let Some(Ok(value)) = self.data.get(offset..).map(Value::from_bytes) {
+                            offset += value.data_len + 3;
 
                             if let Some(flags) = self.data.get(offset) {
                                 offset += 1;
@@ -139,6 +346,35 @@ impl Value {
             Err(CannotConstruct("values::TeaObject::num_entries".to_string(), self))
         }
     }
+
+    /// Decodes an array literal. Arrays on the heap are reached through
+    /// [`Heap::array`](crate::heap::Heap::array) instead.
+    pub(crate) fn as_tea_array(&self) -> Result<TeaArray, CannotConstruct<'_>> {
+        if self.kind != ValueKind::Array {
+            return Err(CannotConstruct("values::TeaArray::kind".to_string(), self));
+        }
+
+        if let Some(Ok(bytes)) = self.data.get(0..=1).map(|v| v.try_into()) {
+            let num_items = u16::from_be_bytes(bytes);
+
+            let mut items: Vec<Value> = Vec::with_capacity(num_items as usize);
+            let mut offset = 2usize;
+
+            while (items.len() as u16) < num_items {
+                if let Some(Ok(value)) = self.data.get(offset..).map(Value::from_bytes) {
+                    offset += value.data_len + 3;
+
+                    items.push(value);
+                } else {
+                    return Err(CannotConstruct(format!("values::TeaArray::item#{}", items.len()), self));
+                }
+            }
+
+            Ok(TeaArray::new(items))
+        } else {
+            Err(CannotConstruct("values::TeaArray::num_items".to_string(), self))
+        }
+    }
 }
 
 pub trait SpecificValue {
@@ -154,6 +390,39 @@ pub struct TeaBool(pub bool);
 #[derive(Debug)]
 pub struct TeaNumber(pub f64);
 pub struct TeaNull;
+/// Raw bytes, which unlike a [`TeaStr`] need not be valid UTF-8.
+#[derive(Debug)]
+pub struct TeaBytes(pub Vec<u8>);
+
+/// String operations count Unicode scalar values, so they never split a UTF-8 sequence.
+impl TeaStr {
+    pub(crate) fn char_len(&self) -> usize {
+        self.0.chars().count()
+    }
+
+    /// The characters from `start` up to `end`. Both ends are clamped to the length of the string.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> String {
+        self.0.chars().take(end).skip(start).collect()
+    }
+
+    pub(crate) fn char_at(&self, idx: usize) -> Option<char> {
+        self.0.chars().nth(idx)
+    }
+
+    /// The character index of the first occurrence of `needle`.
+    pub(crate) fn find(&self, needle: &str) -> Option<usize> {
+        self.0.find(needle).map(|byte| self.0[..byte].chars().count())
+    }
+
+    /// Splits around `sep`, or into single characters if `sep` is empty.
+    pub(crate) fn split(&self, sep: &str) -> Vec<String> {
+        if sep.is_empty() {
+            self.0.chars().map(String::from).collect()
+        } else {
+            self.0.split(sep).map(String::from).collect()
+        }
+    }
+}
 
 impl SpecificValue for TeaStr {
     type Value = String;
@@ -163,7 +432,28 @@ impl SpecificValue for TeaStr {
     }
 
     fn to_value(&self) -> Value {
-        Value::new(Box::from(self.0.as_bytes()))
+        Value::new(ValueKind::Str, Box::from(self.0.as_bytes()))
+    }
+}
+
+impl TeaBytes {
+    /// The bytes from `start` up to `end`. Both ends are clamped to the length.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> Vec<u8> {
+        let end = end.min(self.0.len());
+
+        self.0[start.min(end)..end].to_vec()
+    }
+}
+
+impl SpecificValue for TeaBytes {
+    type Value = Vec<u8>;
+
+    fn value(self) -> Vec<u8> {
+        self.0
+    }
+
+    fn to_value(&self) -> Value {
+        Value::new(ValueKind::Bytes, Box::from(self.0.as_slice()))
     }
 }
 
@@ -176,9 +466,9 @@ impl SpecificValue for TeaBool {
 
     fn to_value(&self) -> Value {
         if self.0 {
-            Value::new(Box::from(TRUE))
+            Value::new(ValueKind::Bool, Box::from(TRUE))
         } else {
-            Value::new(Box::from(FALSE))
+            Value::new(ValueKind::Bool, Box::from(FALSE))
         }
     }
 }
@@ -198,9 +488,9 @@ impl SpecificValue for TeaNumber {
 impl SpecificValue for TeaNull {
     type Value = ();
 
-    fn value(self) -> () {}
+    fn value(self) {}
     fn to_value(&self) -> Value {
-        Value::new(Box::from(FALSE))
+        Value::new(ValueKind::Null, Box::from([]))
     }
 }
 
@@ -276,60 +566,190 @@ impl SpecificValue for TeaFunction {
         let binding = self.code_len.to_be_bytes();
         let code_len = binding.as_slice();
 
-        let code = self.code.as_slice();
+        let code = &self.code;
 
         
         let binding = [code_len, code].concat();
 
         let bytes = binding.as_slice();
 
-        Value::new(Box::from(bytes))
+        Value::new(ValueKind::Function, Box::from(bytes))
     }
 }
 
 #[derive(Debug)]
 pub struct TeaObject {
-    pub entries: HashMap<String, (Value, u8)>,
+    pub entries: OrderedMap<(Value, u8)>,
 }
 
 impl TeaObject {
-    pub(crate) fn new(entries: HashMap<String, (Value, u8)>) -> Self {
+    pub fn new(entries: OrderedMap<(Value, u8)>) -> Self {
         TeaObject { entries }
     }
+
+    /// Sets the entry at `key`, keeping the flags of an existing entry.
+    /// New entries are created without any flags.
+    pub fn set(&mut self, key: &str, value: &Value) -> Result<(), NotAllowed> {
+        if key.len() > u16::MAX as usize {
+            return Err(NotAllowed(format!(
+                "object keys longer than {} bytes",
+                u16::MAX
+            )));
+        }
+
+        match self.entries.get_mut(key) {
+            Some((_, flags)) if *flags & READONLY != 0 => {
+                Err(NotAllowed(format!("writing to read-only entry '{key}'")))
+            }
+
+            Some(entry) => {
+                entry.0 = value.clone();
+
+                Ok(())
+            }
+
+            None => {
+                self.entries.insert(key.to_string(), (value.clone(), 0u8));
+
+                Ok(())
+            }
+        }
+    }
 }
 
 impl SpecificValue for TeaObject {
-    type Value = HashMap<String, (Value, u8)>;
+    type Value = OrderedMap<(Value, u8)>;
 
     fn value(self) -> Self::Value {
         self.entries
     }
 
+    /// Encodes the object as it appears in bytecode.
+    ///
+    /// # Panics
+    ///
+    /// If it has more than 65535 entries, or an entry is too long to encode.
     fn to_value(&self) -> Value {
-        let mut bytes: Vec<u8> = vec![];
+        let mut bytes: Vec<u8> = count(self.entries.len()).unwrap_or_else(|e| e.raise()).to_vec();
 
-        let binding = (self.entries.len() as u16).to_be_bytes();
-        let entries_size = binding.as_slice();
+        for (key, (value, flags)) in self.entries.iter() {
+            bytes.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&value.to_bytes().unwrap_or_else(|e| e.raise()));
+            bytes.push(*flags);
+        }
 
-        for entry in self.entries.clone() {
-            let binding = (entry.0.len() as u16).to_be_bytes();
-            let key_len = binding.as_slice();
+        Value::new(ValueKind::Object, Box::from(bytes.as_slice()))
+    }
+}
 
-            let entry = [
-                key_len,
-                entry.0.as_bytes(),
-                entry.1 .0.to_bytes().as_slice(),
-                &[entry.1 .1],
-            ]
-            .concat();
+#[derive(Debug)]
+pub struct TeaArray {
+    pub items: Vec<Value>,
+}
 
-            for data in entry {
-                bytes.push(data)
-            }
+impl TeaArray {
+    pub fn new(items: Vec<Value>) -> Self {
+        TeaArray { items }
+    }
+
+    fn index(&self, idx: f64) -> Option<usize> {
+        if idx.fract() == 0. && idx >= 0. && (idx as usize) < self.items.len() {
+            Some(idx as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, idx: f64) -> Option<&Value> {
+        self.index(idx).map(|idx| &self.items[idx])
+    }
+
+    /// Replaces the item at `idx`, returning `None` if it is out of bounds.
+    pub fn set(&mut self, idx: f64, value: &Value) -> Option<()> {
+        let idx = self.index(idx)?;
+
+        self.items[idx] = value.clone();
+
+        Some(())
+    }
+}
+
+impl SpecificValue for TeaArray {
+    type Value = Vec<Value>;
+
+    fn value(self) -> Self::Value {
+        self.items
+    }
+
+    /// Encodes the array as it appears in bytecode.
+    ///
+    /// # Panics
+    ///
+    /// If it has more than 65535 items, or an item is too long to encode.
+    fn to_value(&self) -> Value {
+        let mut bytes: Vec<u8> = count(self.items.len()).unwrap_or_else(|e| e.raise()).to_vec();
+
+        for item in &self.items {
+            bytes.extend_from_slice(&item.to_bytes().unwrap_or_else(|e| e.raise()));
         }
 
-        let complete_bytes = [entries_size, bytes.as_slice()].concat();
+        Value::new(ValueKind::Array, Box::from(bytes.as_slice()))
+    }
+}
+
+/// The u16 count that starts an encoded object or array.
+fn count(len: usize) -> Result<[u8; 2], NotAllowed> {
+    u16::try_from(len)
+        .map(u16::to_be_bytes)
+        .map_err(|_| too_long("entries", len))
+}
+
+fn too_long(what: &str, len: usize) -> NotAllowed {
+    NotAllowed(format!("encoding {len} {what} in bytecode, which holds at most {}", u16::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_values_keep_their_length() {
+        let value = TeaStr("a".repeat(100_000)).to_value();
+
+        assert_eq!(value.data_len, 100_000);
+        assert_eq!(value.as_tea_string().ok().map(|str| str.char_len()), Some(100_000));
+    }
+
+    #[test]
+    fn short_values_round_trip_through_bytecode() {
+        let array = TeaArray::new(vec![TeaNumber(1.).to_value(), TeaStr("two".into()).to_value()]).to_value();
+        let items = array.as_tea_array().ok().map(|array| array.items).unwrap_or_default();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_tea_number().ok().map(|n| n.value()), Some(1.));
+        assert_eq!(items[1].as_tea_string().ok().map(|str| str.value()), Some("two".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 65535")]
+    fn long_values_cannot_be_encoded() {
+        TeaArray::new(vec![TeaStr("a".repeat(70_000)).to_value()]).to_value();
+    }
+
+    #[test]
+    fn malformed_bytecode_cannot_be_decoded() {
+        let number = [&[ValueKind::Number as u8, 0, 8][..], &1f64.to_be_bytes()].concat();
+
+        assert_eq!(Value::from_bytes(&number).map(|value| value.data_len).ok(), Some(8));
+        assert!(Value::from_bytes(&number[..10]).is_err());
+        assert!(Value::from_bytes(&number[..2]).is_err());
+        assert!(Value::from_bytes(&[]).is_err());
+        assert!(Value::from_bytes(&[200, 0, 0]).is_err());
+
+        let entry = [&1u16.to_be_bytes()[..], &1u16.to_be_bytes(), b"k", &number[..10]].concat();
 
-        Value::new(Box::from(complete_bytes.as_slice()))
+        assert!(Value::new(ValueKind::Object, entry.into()).as_tea_object().is_err());
+        assert!(Value::new(ValueKind::Array, [0, 1, 200, 0, 0].into()).as_tea_array().is_err());
     }
 }
@@ -0,0 +1,2558 @@
+use crate::backend::IoBackend;
+use crate::display::DisplayValue;
+use crate::env;
+use crate::errors::{
+    CannotConstruct, DeallocatedError, EncodingError, NoValueError, NotAllowed, RuntimeError, VmError,
+};
+use crate::files::{Files, StdoutBuffering};
+use crate::format::format;
+use crate::fs;
+use crate::heap::{GcConfig, Heap, HeapStats};
+use crate::math;
+use crate::opcodes;
+use crate::permissions::{Access, Permissions};
+use crate::random::{self, Rng};
+use crate::scope::{Scope, ARGS_VAR, HOST_VAR};
+use crate::values::{
+    SpecificValue, TeaArray, TeaBool, TeaBytes, TeaNull, TeaNumber, TeaStr, Value, ValueKind, READONLY,
+};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// A function implemented in Rust that bytecode can call. It receives its arguments in the order
+/// they were pushed.
+pub type NativeFn = dyn Fn(&mut Vm, &[Value]) -> Result<Value, VmError>;
+
+struct Native {
+    arity: usize,
+    f: Rc<NativeFn>,
+}
+
+fn combine_u8_to_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    if offset >= bytes.len() || offset + 4 > bytes.len() {
+        return None;
+    }
+
+    Some(u32::from_be_bytes(
+        bytes[offset..offset + 4]
+            .try_into()
+            .expect("Failed to convert bytes to u32"),
+    ))
+}
+
+fn combine_u8_to_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    if offset >= bytes.len() || offset + 2 > bytes.len() {
+        return None;
+    }
+
+    Some(u16::from_be_bytes(
+        bytes[offset..offset + 2]
+            .try_into()
+            .expect("Failed to convert bytes to u16"),
+    ))
+}
+
+/// Finds where the marker `idx` ends. Markers that have not run yet are found by scanning
+/// forward from `pc`, so jumps can skip ahead.
+fn find_label(
+    bytes: &[u8],
+    labels: &mut HashMap<u32, usize>,
+    pc: usize,
+    idx: u32,
+) -> Result<usize, VmError> {
+    let mut pos = pc;
+
+    while !labels.contains_key(&idx) {
+        let len = opcodes::instruction_len(bytes, pos)
+            .ok_or_else(|| NoValueError(format!("GOTO::marker(idx = {idx})")))?;
+
+        if bytes[pos] == opcodes::MARKER {
+            if let Some(marker) = combine_u8_to_u32(bytes, pos + 1) {
+                labels.insert(marker, pos + len);
+            }
+        }
+
+        pos += len;
+    }
+
+    Ok(labels[&idx])
+}
+
+/// Runs bytecode, and holds everything that outlives a single call: the heap, the global scope,
+/// open files, the random number generator and the native functions registered by the embedder.
+pub struct Vm {
+    pub(crate) heap: Heap,
+    global: Box<Scope>,
+    /// The scope of each call that is running, innermost last.
+    frames: Vec<*mut Scope>,
+    natives: Vec<Native>,
+    pub(crate) files: Files,
+    pub(crate) random: Rng,
+    exit_status: Option<i32>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::with_gc_config(GcConfig::default())
+    }
+
+    /// # Panics
+    ///
+    /// If `config` is not valid, as checked by [`GcConfig::validate`].
+    pub fn with_gc_config(config: GcConfig) -> Self {
+        let mut heap = Heap::with_config(config);
+        let global = Box::new(Scope::new_global(&mut heap));
+
+        let mut vm = Vm {
+            heap,
+            global,
+            frames: Vec::new(),
+            natives: Vec::new(),
+            files: Files::new(),
+            random: Rng::new(random::DEFAULT_SEED),
+            exit_status: None,
+        };
+
+        env::register(&mut vm).unwrap_or_else(|e| e.raise());
+        fs::register(&mut vm).unwrap_or_else(|e| e.raise());
+        math::register(&mut vm).unwrap_or_else(|e| e.raise());
+        random::register(&mut vm).unwrap_or_else(|e| e.raise());
+
+        vm
+    }
+
+    /// Runs `bytes` in the global scope. Running `EXIT` ends the run successfully, and its
+    /// status is kept for [`Vm::exit_status`].
+    pub fn run(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        let global: *mut Scope = &mut *self.global;
+
+        self.exit_status = None;
+
+        match self.exec(bytes, global) {
+            Err(VmError::Exit(status)) => {
+                self.exit_status = Some(status);
+
+                Ok(())
+            }
+
+            result => result,
+        }
+    }
+
+    /// The status passed to `EXIT`, if the last run ended with it.
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    /// Sets the `args` array of the global scope.
+    pub fn set_args(&mut self, args: &[String]) {
+        let items = args.iter().map(|arg| TeaStr(arg.clone()).to_value()).collect();
+        let array = self.heap.alloc_array(TeaArray::new(items));
+
+        self.set_global(ARGS_VAR, &array);
+    }
+
+    /// Restarts the generator behind the `random` object of the global scope from `seed`. A new
+    /// VM always starts from the same seed, so runs are reproducible until this is called.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Rng::new(seed);
+    }
+
+    /// Changes how output written to stdout is buffered. Anything already buffered is written
+    /// first.
+    pub fn set_stdout_buffering(&mut self, buffering: StdoutBuffering) -> Result<(), VmError> {
+        self.files.set_buffering(buffering)
+    }
+
+    /// Sends the standard streams and files of bytecode to `io` from now on.
+    pub fn set_io_backend(&mut self, io: impl IoBackend + 'static) -> Result<(), VmError> {
+        self.files.set_io(Box::new(io))
+    }
+
+    pub fn permissions(&self) -> &Permissions {
+        self.files.permissions()
+    }
+
+    /// Limits what bytecode may do outside the VM from now on. Files that are already open stay
+    /// open.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.files.set_permissions(permissions)
+    }
+
+    /// Every file opened and environment variable read so far, and each standard stream used,
+    /// in the order they were first allowed.
+    pub fn audit_log(&self) -> &[Access] {
+        self.files.audit_log()
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    /// Frees every object and array that running bytecode can no longer reach.
+    ///
+    /// Values held only by Rust code, such as the arguments or results of [`Vm::call`], are not
+    /// roots, so they must be stored somewhere bytecode can reach before collecting.
+    pub fn collect_garbage(&mut self) -> Result<(), VmError> {
+        let scope = match self.frames.last() {
+            Some(scope) => unsafe { scope.as_ref() }
+                .ok_or_else(|| DeallocatedError("Vm::frames".into()))?,
+            None => &self.global,
+        };
+
+        self.heap.collect(scope);
+
+        Ok(())
+    }
+
+    pub fn get_global(&self, idx: u32) -> Option<Value> {
+        self.global.get_var(&idx)
+    }
+
+    pub fn set_global(&mut self, idx: u32, value: &Value) {
+        self.global.set_var(&idx, value)
+    }
+
+    /// Creates a function value that calls `f` with the top `arity` values of the stack and
+    /// pushes its result. The value can be stored anywhere a bytecode function can.
+    pub fn native<F>(&mut self, name: &str, arity: usize, f: F) -> Value
+    where
+        F: Fn(&mut Vm, &[Value]) -> Result<Value, VmError> + 'static,
+    {
+        self.natives.push(Native {
+            arity,
+            f: Rc::new(f),
+        });
+
+        Value::from_native((self.natives.len() - 1) as u32, name)
+    }
+
+    /// Registers `f` under `name` in the `host` object of the global scope, where bytecode can
+    /// reach it with `LOAD HOST_VAR`, `GET name` and `CALL`.
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, f: F) -> Result<(), VmError>
+    where
+        F: Fn(&mut Vm, &[Value]) -> Result<Value, VmError> + 'static,
+    {
+        self.register_native_in(HOST_VAR, name, arity, f)
+    }
+
+    /// Registers `f` under `name` in the object held by the global variable `global`, such as
+    /// `io` or `math`. The entry is read-only.
+    pub fn register_native_in<F>(
+        &mut self,
+        global: u32,
+        name: &str,
+        arity: usize,
+        f: F,
+    ) -> Result<(), VmError>
+    where
+        F: Fn(&mut Vm, &[Value]) -> Result<Value, VmError> + 'static,
+    {
+        let object = self
+            .get_global(global)
+            .filter(|object| object.kind == ValueKind::Object)
+            .ok_or_else(|| NoValueError(format!("Vm::global(idx = {global})::Object")))?;
+
+        let function = self.native(name, arity, f);
+
+        self.heap
+            .object_mut(&object)?
+            .entries
+            .insert(name.to_string(), (function, READONLY));
+
+        Ok(())
+    }
+
+    /// Calls a function value, such as a callback that bytecode stored in a variable, and returns
+    /// what it left on its stack in the order it was pushed.
+    ///
+    /// `args` start out on the callee's stack. The callee's scope is a child of the innermost
+    /// call that is running, or of the global scope when nothing is, so it can be used both
+    /// from native functions and between runs. If the function runs `EXIT`, the error is
+    /// [`VmError::Exit`], which native functions should return as it is so the run ends.
+    pub fn call(&mut self, function: &Value, args: &[Value]) -> Result<Vec<Value>, VmError> {
+        let parent = match self.frames.last() {
+            Some(scope) => *scope,
+            None => &mut *self.global,
+        };
+
+        let mut callee = Scope::new(Some(parent));
+
+        for arg in args {
+            callee.push(arg);
+        }
+
+        if function.kind == ValueKind::Native {
+            self.call_native(function, &mut callee)?;
+        } else {
+            let f = function.as_tea_function()?;
+
+            self.exec(&f.code, &mut callee)?;
+        }
+
+        Ok(callee.take_stack())
+    }
+
+    fn call_native(&mut self, function: &Value, scope: *mut Scope) -> Result<(), VmError> {
+        let native = function
+            .native_index()
+            .and_then(|idx| self.natives.get(idx as usize))
+            .ok_or_else(|| NoValueError("CALL::native".to_string()))?;
+
+        let (arity, f) = (native.arity, native.f.clone());
+
+        let scope = unsafe { scope.as_mut() }
+            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+        scope.require("CALL", arity)?;
+
+        // The arguments stay on the stack during the call so the garbage collector can see them.
+        let args = scope.top(arity);
+        let result = f(self, &args)?;
+
+        for _ in 0..arity {
+            scope.pop();
+        }
+
+        scope.push(&result);
+
+        Ok(())
+    }
+
+    /// Runs `bytes` in `scope` until it ends or reaches `RET`.
+    pub(crate) fn exec(&mut self, bytes: &[u8], scope: *mut Scope) -> Result<(), VmError> {
+        self.frames.push(scope);
+
+        let result = self.exec_frame(bytes, scope);
+
+        self.frames.pop();
+
+        result
+    }
+
+    fn exec_frame(&mut self, bytes: &[u8], scope: *mut Scope) -> Result<(), VmError> {
+        let mut pc = 0usize;
+        let mut labels = HashMap::new();
+
+        while pc < bytes.len() {
+            unsafe {
+                if self.heap.should_collect() {
+                    self.collect_garbage()?;
+                }
+
+                let opcode = bytes[pc];
+
+                match opcode {
+                    opcodes::PUSH => {
+                        pc += 1;
+
+                        let value = Value::from_bytes(&bytes[pc..])?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&self.heap.instantiate(&value)?);
+
+                        pc += value.data_len + 3;
+                    }
+
+                    opcodes::DUP => {
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("DUP", 1)?;
+
+                        let a = scope.pop().unwrap();
+
+                        scope.push(&a);
+                        scope.push(&a);
+                    }
+
+                    opcodes::POP => {
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("POP", 1)?;
+                        scope.pop();
+                    }
+
+                    opcodes::SWAP => {
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("SWAP", 2)?;
+
+                        let b = scope.pop().unwrap();
+                        let a = scope.pop().unwrap();
+
+                        scope.push(&b);
+                        scope.push(&a);
+                    }
+
+                    opcodes::OVER => {
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("OVER", 2)?;
+
+                        let b = scope.pop().unwrap();
+                        let a = scope.pop().unwrap();
+
+                        scope.push(&a);
+                        scope.push(&b);
+                        scope.push(&a);
+                    }
+
+                    opcodes::ROT => {
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("ROT", 3)?;
+
+                        let c = scope.pop().unwrap();
+                        let b = scope.pop().unwrap();
+                        let a = scope.pop().unwrap();
+
+                        scope.push(&b);
+                        scope.push(&c);
+                        scope.push(&a);
+                    }
+
+                    opcodes::STORE => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("Store::stack::value".into()))?;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("STORE::idx".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .set_var(&idx, &value);
+
+                        pc += 4;
+                    }
+
+                    opcodes::LOAD | opcodes::LOAD_OPT => {
+                        pc += 1;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("LOAD::idx".to_string()))?;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .get_var(&idx)
+                            .clone()
+                            .or_else(|| (opcode == opcodes::LOAD_OPT).then(|| TeaNull.to_value()))
+                            .ok_or_else(|| NoValueError(format!("Load::variable(idx = {})", idx)))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&value);
+
+                        pc += 4;
+                    }
+
+                    opcodes::NADD => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NADD::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NADD::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&Value::from_specific(&TeaNumber(
+                                left.value() + right.value(),
+                            )))
+                    }
+
+                    opcodes::NSUB => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NSUB::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NSUB::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&(left - right).to_value())
+                    }
+
+                    opcodes::NMUL => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NMUL::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NMUL::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&(left * right).to_value())
+                    }
+
+                    opcodes::NDIV => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NDIV::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NDIV::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&(left / right).to_value())
+                    }
+
+                    opcodes::NMOD => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NMOD::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NMOD::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&(left % right).to_value())
+                    }
+
+                    opcodes::NPOW => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NPOW::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NPOW::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&left.pow(right).to_value())
+                    }
+
+                    opcodes::SMUL => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SMUL::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SMUL::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&Value::from_specific(&TeaStr(
+                                left.value().repeat(right.value() as usize),
+                            )))
+                    }
+
+                    opcodes::SCONCAT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SCONCAT::stack::right".to_string()))?
+                            .as_tea_string()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SCONCAT::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(left.value() + &right.value()).to_value());
+                    }
+
+                    opcodes::SLEN => {
+                        pc += 1;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLEN::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(str.char_len() as f64).to_value());
+                    }
+
+                    opcodes::SSLICE => {
+                        pc += 1;
+
+                        let end = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SSLICE::stack::end".to_string()))?
+                            .as_tea_index()?;
+
+                        let start = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SSLICE::stack::start".to_string()))?
+                            .as_tea_index()?;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SSLICE::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(str.slice(start, end)).to_value());
+                    }
+
+                    opcodes::SCHAR_AT => {
+                        pc += 1;
+
+                        let idx = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SCHAR_AT::stack::idx".to_string()))?
+                            .as_tea_index()?;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SCHAR_AT::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        let char = str
+                            .char_at(idx)
+                            .ok_or_else(|| NoValueError(format!("SCHAR_AT::stack::str::{idx}")))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(char.to_string()).to_value());
+                    }
+
+                    opcodes::SFIND => {
+                        pc += 1;
+
+                        let needle = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SFIND::stack::needle".to_string()))?
+                            .as_tea_string()?;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SFIND::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(str.find(&needle.0).map_or(-1., |idx| idx as f64)).to_value());
+                    }
+
+                    opcodes::SSPLIT => {
+                        pc += 1;
+
+                        let sep = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SSPLIT::stack::sep".to_string()))?
+                            .as_tea_string()?;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SSPLIT::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        let items = str
+                            .split(&sep.0)
+                            .into_iter()
+                            .map(|item| TeaStr(item).to_value())
+                            .collect();
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&self.heap.alloc_array(TeaArray::new(items)));
+                    }
+
+                    opcodes::SJOIN => {
+                        pc += 1;
+
+                        let sep = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SJOIN::stack::sep".to_string()))?
+                            .as_tea_string()?;
+
+                        let array = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SJOIN::stack::array".to_string()))?;
+
+                        let items = self.heap
+                            .array(&array)?
+                            .items
+                            .iter()
+                            .map(|item| Ok(item.as_tea_string()?.value()))
+                            .collect::<Result<Vec<String>, VmError>>()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(items.join(&sep.0)).to_value());
+                    }
+
+                    opcodes::SUPPER => {
+                        pc += 1;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SUPPER::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(str.0.to_uppercase()).to_value());
+                    }
+
+                    opcodes::SLOWER => {
+                        pc += 1;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLOWER::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(str.0.to_lowercase()).to_value());
+                    }
+
+                    opcodes::STRIM => {
+                        pc += 1;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("STRIM::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(str.0.trim().to_string()).to_value());
+                    }
+
+                    opcodes::FORMAT => {
+                        pc += 1;
+
+                        let count = *bytes.get(pc).ok_or_else(|| NoValueError("FORMAT::count".to_string()))? as usize;
+
+                        pc += 1;
+
+                        let scope = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?;
+
+                        scope.require("FORMAT", count + 1)?;
+
+                        let mut args: Vec<Value> = (0..count).map(|_| scope.pop().unwrap()).collect();
+
+                        args.reverse();
+
+                        let template = scope
+                            .pop()
+                            .unwrap()
+                            .as_tea_string()?;
+
+                        let str = format(&self.heap, &template.0, &args)?;
+
+                        scope.push(&TeaStr(str).to_value());
+                    }
+
+                    opcodes::TONUM => {
+                        pc += 1;
+
+                        let mode = *bytes.get(pc).ok_or_else(|| NoValueError("TONUM::mode".to_string()))?;
+
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("TONUM::stack::value".to_string()))?;
+
+                        let number = match value.coerce_number() {
+                            Ok(number) => number.to_value(),
+                            Err(_) if mode == opcodes::ON_FAIL_NULL => TeaNull.to_value(),
+                            Err(e) if mode == opcodes::ON_FAIL_RAISE => return Err(e.into()),
+                            Err(_) => return Err(NotAllowed(format!("conversion failure mode {mode}")).into()),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&number);
+                    }
+
+                    opcodes::TOBOOL => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("TOBOOL::stack::value".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(value.is_truthy()).to_value());
+                    }
+
+                    opcodes::PARSE_INT => {
+                        pc += 1;
+
+                        let mode = *bytes.get(pc).ok_or_else(|| NoValueError("PARSE_INT::mode".to_string()))?;
+
+                        pc += 1;
+
+                        let radix = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("PARSE_INT::stack::radix".to_string()))?
+                            .as_tea_number()?;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("PARSE_INT::stack::str".to_string()))?;
+
+                        let number = match str.parse_int(radix.value()) {
+                            Ok(number) => number.to_value(),
+                            Err(_) if mode == opcodes::ON_FAIL_NULL => TeaNull.to_value(),
+                            Err(e) if mode == opcodes::ON_FAIL_RAISE => return Err(e.into()),
+                            Err(_) => return Err(NotAllowed(format!("conversion failure mode {mode}")).into()),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&number);
+                    }
+
+                    opcodes::CALL => {
+                        pc += 1;
+
+                        let f = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("CALL::stack::fn".to_string()))?;
+
+                        if f.kind == ValueKind::Native {
+                            self.call_native(&f, scope)?;
+                        } else {
+                            let f = f.as_tea_function()?;
+
+                            self.exec(&f.code, &mut Scope::new(Option::from(scope)))?;
+                        }
+                    }
+
+                    opcodes::RET => return Ok(()),
+
+                    opcodes::GET | opcodes::GET_OPT => {
+                        pc += 1;
+
+                        let obj = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("GET::stack::obj".to_string()))?;
+
+                        let key_len = combine_u8_to_u16(bytes, pc)
+                            .ok_or_else(|| NoValueError("GET::key_len".to_string()))?
+                            as usize;
+
+                        pc += 2;
+
+                        let key = bytes
+                            .get(pc..pc + key_len)
+                            .and_then(|key| String::from_utf8(key.to_vec()).ok())
+                            .ok_or_else(|| NoValueError("GET::key".to_string()))?;
+
+                        pc += key_len;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(
+                                &self.heap
+                                    .object(&obj)?
+                                    .entries
+                                    .get(&key)
+                                    .map(|entry| entry.0.clone())
+                                    .or_else(|| (opcode == opcodes::GET_OPT).then(|| TeaNull.to_value()))
+                                    .ok_or_else(|| NoValueError(format!("GET::stack::Object::{key}")))?,
+                            );
+                    }
+
+                    opcodes::GET_DYN | opcodes::GET_DYN_OPT => {
+                        pc += 1;
+
+                        let key = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("GET_DYN::stack::key".to_string()))?;
+
+                        let obj = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("GET_DYN::stack::obj".to_string()))?;
+
+                        let value = if obj.kind == ValueKind::Array {
+                            let idx = key.as_tea_number()?.value();
+
+                            self.heap.array(&obj)?
+                                .get(idx)
+                                .cloned()
+                                .or_else(|| (opcode == opcodes::GET_DYN_OPT).then(|| TeaNull.to_value()))
+                                .ok_or_else(|| NoValueError(format!("GET_DYN::stack::Array::{idx}")))?
+                        } else {
+                            let key = key.as_tea_string()?.value();
+
+                            self.heap.object(&obj)?
+                                .entries
+                                .get(&key)
+                                .map(|entry| entry.0.clone())
+                                .or_else(|| (opcode == opcodes::GET_DYN_OPT).then(|| TeaNull.to_value()))
+                                .ok_or_else(|| NoValueError(format!("GET_DYN::stack::Object::{key}")))?
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&value);
+                    }
+
+                    opcodes::SET_DYN => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SET_DYN::stack::value".to_string()))?;
+
+                        let key = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SET_DYN::stack::key".to_string()))?;
+
+                        let obj = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SET_DYN::stack::obj".to_string()))?;
+
+                        if obj.kind == ValueKind::Array {
+                            let idx = key.as_tea_number()?.value();
+
+                            self.heap.array_mut(&obj)?
+                                .set(idx, &value)
+                                .ok_or_else(|| NoValueError(format!("SET_DYN::stack::Array::{idx}")))?;
+                        } else {
+                            let key = key.as_tea_string()?.value();
+
+                            self.heap.object_mut(&obj)?
+                                .set(&key, &value)?;
+                        }
+                    }
+
+                    opcodes::WRITE => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("WRITE::stack::value".to_string()))?;
+
+                        let content = match value.kind {
+                            ValueKind::Bytes => value.as_tea_bytes()?.value(),
+                            _ => value.as_tea_string()?.value().into_bytes(),
+                        };
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("WRITE::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        self.files.write(fd, &content)?;
+                    }
+
+                    opcodes::EXIT => {
+                        let status = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("EXIT::stack::status".to_string()))?
+                            .as_tea_integer()?;
+
+                        let status = i32::try_from(status)
+                            .map_err(|_| NotAllowed(format!("exit status {status}")))?;
+
+                        return Err(VmError::Exit(status));
+                    }
+
+                    opcodes::FLUSH => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("FLUSH::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        self.files.flush(fd)?;
+                    }
+
+                    opcodes::OPEN => {
+                        pc += 1;
+
+                        let mode = *bytes.get(pc).ok_or_else(|| NoValueError("OPEN::mode".to_string()))?;
+
+                        pc += 1;
+
+                        let path = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("OPEN::stack::path".to_string()))?
+                            .as_tea_string()?;
+
+                        let fd = self.files.open(Path::new(&path.0), mode)?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(fd as f64).to_value());
+                    }
+
+                    opcodes::CLOSE => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("CLOSE::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        self.files.close(fd)?;
+                    }
+
+                    opcodes::READLN => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READLN::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let line = match self.files.read_line(fd)? {
+                            Some(line) => TeaStr(line).to_value(),
+                            None => TeaNull.to_value(),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&line);
+                    }
+
+                    opcodes::READ_ALL => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ_ALL::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let str = self.files.read_all(fd)?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(str).to_value());
+                    }
+
+                    opcodes::READ => {
+                        pc += 1;
+
+                        let count = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ::stack::count".to_string()))?
+                            .as_tea_index()?;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let value = match self.files.read_chars(fd, count)? {
+                            Some(str) => TeaStr(str).to_value(),
+                            None => TeaNull.to_value(),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&value);
+                    }
+
+                    opcodes::EOF => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("EOF::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let eof = self.files.at_eof(fd)?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(eof).to_value());
+                    }
+
+                    opcodes::BYTES => {
+                        pc += 1;
+
+                        let array = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES::stack::array".to_string()))?;
+
+                        let bytes = self.heap
+                            .array(&array)?
+                            .items
+                            .iter()
+                            .map(|item| {
+                                u8::try_from(item.as_tea_integer()?)
+                                    .map_err(|_| CannotConstruct("values::TeaBytes::byte".to_string(), item).into())
+                            })
+                            .collect::<Result<Vec<u8>, VmError>>()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBytes(bytes).to_value());
+                    }
+
+                    opcodes::BYTES_LEN => {
+                        pc += 1;
+
+                        let bytes = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_LEN::stack::bytes".to_string()))?
+                            .as_tea_bytes()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(bytes.0.len() as f64).to_value());
+                    }
+
+                    opcodes::BYTES_GET => {
+                        pc += 1;
+
+                        let idx = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_GET::stack::idx".to_string()))?
+                            .as_tea_index()?;
+
+                        let bytes = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_GET::stack::bytes".to_string()))?
+                            .as_tea_bytes()?;
+
+                        let byte = bytes
+                            .0
+                            .get(idx)
+                            .ok_or_else(|| NoValueError(format!("BYTES_GET::stack::bytes::{idx}")))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(*byte as f64).to_value());
+                    }
+
+                    opcodes::BYTES_SLICE => {
+                        pc += 1;
+
+                        let end = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_SLICE::stack::end".to_string()))?
+                            .as_tea_index()?;
+
+                        let start = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_SLICE::stack::start".to_string()))?
+                            .as_tea_index()?;
+
+                        let bytes = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_SLICE::stack::bytes".to_string()))?
+                            .as_tea_bytes()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBytes(bytes.slice(start, end)).to_value());
+                    }
+
+                    opcodes::BYTES_CONCAT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_CONCAT::stack::right".to_string()))?
+                            .as_tea_bytes()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BYTES_CONCAT::stack::left".to_string()))?
+                            .as_tea_bytes()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBytes([left.value(), right.value()].concat()).to_value());
+                    }
+
+                    opcodes::ENCODE => {
+                        pc += 1;
+
+                        let str = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ENCODE::stack::str".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBytes(str.value().into_bytes()).to_value());
+                    }
+
+                    opcodes::DECODE => {
+                        pc += 1;
+
+                        let mode = *bytes.get(pc).ok_or_else(|| NoValueError("DECODE::mode".to_string()))?;
+
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("DECODE::stack::bytes".to_string()))?
+                            .as_tea_bytes()?;
+
+                        let str = match String::from_utf8(value.value()) {
+                            Ok(str) => TeaStr(str).to_value(),
+                            Err(_) if mode == opcodes::ON_FAIL_NULL => TeaNull.to_value(),
+                            Err(e) if mode == opcodes::ON_FAIL_RAISE => {
+                                return Err(EncodingError(format!("the bytes are not valid UTF-8: {}", e.utf8_error())).into())
+                            }
+                            Err(_) => return Err(NotAllowed(format!("conversion failure mode {mode}")).into()),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&str);
+                    }
+
+                    opcodes::READ_BYTES => {
+                        pc += 1;
+
+                        let count = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ_BYTES::stack::count".to_string()))?
+                            .as_tea_index()?;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ_BYTES::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let value = match self.files.read_bytes(fd, count)? {
+                            Some(bytes) => TeaBytes(bytes).to_value(),
+                            None => TeaNull.to_value(),
+                        };
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&value);
+                    }
+
+                    opcodes::READ_ALL_BYTES => {
+                        pc += 1;
+
+                        let fd = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("READ_ALL_BYTES::stack::fd".to_string()))?
+                            .as_tea_number()?
+                            .0 as u32;
+
+                        let bytes = self.files.read_all_bytes(fd)?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBytes(bytes).to_value());
+                    }
+
+                    opcodes::MARKER => {
+                        pc += 1;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("MARKER::idx".to_string()))?;
+                        
+                        pc += 4;
+                        
+                        labels.insert(idx, pc);
+                    }
+                    
+                    opcodes::GOTO => {
+                        pc += 1;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("GOTO::idx".to_string()))?;
+
+                        pc = find_label(bytes, &mut labels, pc + 4, idx)?;
+                    }
+                    
+                    opcodes::GOTO_IF => {
+                        pc += 1;
+                        
+                        let cond =
+                            scope.as_mut()
+                                .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                                .pop()
+                                .ok_or_else(|| NoValueError("GOTO_IF::stack::cond".to_string()))?
+                                .as_tea_bool()?;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("GOTO_IF::idx".to_string()))?;
+
+                        if cond.value() {
+                            pc = find_label(bytes, &mut labels, pc + 4, idx)?;
+                        } else {
+                            pc += 4;
+                        }
+                    }
+
+                    opcodes::ISNULL => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ISNULL::stack::value".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(value.kind == ValueKind::Null).to_value());
+                    }
+
+                    opcodes::GOTO_IF_NULL => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("GOTO_IF_NULL::stack::value".to_string()))?;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("GOTO_IF_NULL::idx".to_string()))?;
+
+                        if value.kind == ValueKind::Null {
+                            pc = find_label(bytes, &mut labels, pc + 4, idx)?;
+                        } else {
+                            pc += 4;
+                        }
+                    }
+
+                    opcodes::COALESCE => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("COALESCE::stack::value".to_string()))?;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("COALESCE::idx".to_string()))?;
+
+                        if value.kind != ValueKind::Null {
+                            scope
+                                .as_mut()
+                                .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                                .push(&value);
+
+                            pc = find_label(bytes, &mut labels, pc + 4, idx)?;
+                        } else {
+                            pc += 4;
+                        }
+                    }
+
+                    opcodes::GOTO_IF_NOT => {
+                        pc += 1;
+
+                        let cond = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("GOTO_IF_NOT::stack::cond".to_string()))?
+                            .as_tea_bool()?;
+
+                        let idx = combine_u8_to_u32(bytes, pc)
+                            .ok_or_else(|| NoValueError("GOTO_IF_NOT::idx".to_string()))?;
+
+                        if !cond.value() {
+                            pc = find_label(bytes, &mut labels, pc + 4, idx)?;
+                        } else {
+                            pc += 4;
+                        }
+                    }
+                    
+                    opcodes::EQ => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("EQ::stack::right".to_string()))?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("EQ::stack::left".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(self.heap.equals(&left, &right)).to_value())
+                    }
+
+                    opcodes::NGT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NGT::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NGT::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() > right.value()).to_value())
+                    }
+
+                    opcodes::NLT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NLT::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NLT::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() < right.value()).to_value())
+                    }
+
+                    opcodes::NOT => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NOT::stack::value".to_string()))?
+                            .as_tea_bool()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(!value.value()).to_value())
+                    }
+
+                    opcodes::AND => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("AND::stack::right".to_string()))?
+                            .as_tea_bool()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("AND::stack::left".to_string()))?
+                            .as_tea_bool()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() && right.value()).to_value())
+                    }
+
+                    opcodes::OR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("OR::stack::right".to_string()))?
+                            .as_tea_bool()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("OR::stack::left".to_string()))?
+                            .as_tea_bool()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() || right.value()).to_value())
+                    }
+
+                    opcodes::NEQ => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NEQ::stack::right".to_string()))?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NEQ::stack::left".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(!self.heap.equals(&left, &right)).to_value())
+                    }
+
+                    opcodes::NGE => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NGE::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NGE::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() >= right.value()).to_value())
+                    }
+
+                    opcodes::NLE => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NLE::stack::right".to_string()))?
+                            .as_tea_number()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("NLE::stack::left".to_string()))?
+                            .as_tea_number()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() <= right.value()).to_value())
+                    }
+
+                    opcodes::SGT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SGT::stack::right".to_string()))?
+                            .as_tea_string()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SGT::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() > right.value()).to_value())
+                    }
+
+                    opcodes::SLT => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLT::stack::right".to_string()))?
+                            .as_tea_string()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLT::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() < right.value()).to_value())
+                    }
+
+                    opcodes::SGE => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SGE::stack::right".to_string()))?
+                            .as_tea_string()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SGE::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() >= right.value()).to_value())
+                    }
+
+                    opcodes::SLE => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLE::stack::right".to_string()))?
+                            .as_tea_string()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SLE::stack::left".to_string()))?
+                            .as_tea_string()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaBool(left.value() <= right.value()).to_value())
+                    }
+
+                    opcodes::BAND => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BAND::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BAND::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber((left & right) as f64).to_value())
+                    }
+
+                    opcodes::BOR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BOR::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BOR::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber((left | right) as f64).to_value())
+                    }
+
+                    opcodes::BXOR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BXOR::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BXOR::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber((left ^ right) as f64).to_value())
+                    }
+
+                    opcodes::BNOT => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("BNOT::stack::value".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(!value as f64).to_value())
+                    }
+
+                    opcodes::SHL => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SHL::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SHL::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(left.wrapping_shl(right as u32) as f64).to_value())
+                    }
+
+                    opcodes::SHR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SHR::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("SHR::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(left.wrapping_shr(right as u32) as f64).to_value())
+                    }
+
+                    opcodes::USHR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("USHR::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("USHR::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber((left as u32).wrapping_shr(right as u32) as f64).to_value())
+                    }
+
+                    opcodes::ROTL => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ROTL::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ROTL::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(left.rotate_left(right as u32 % 32) as f64).to_value())
+                    }
+
+                    opcodes::ROTR => {
+                        pc += 1;
+
+                        let right = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ROTR::stack::right".to_string()))?
+                            .as_tea_int32()?;
+
+                        let left = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("ROTR::stack::left".to_string()))?
+                            .as_tea_int32()?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaNumber(left.rotate_right(right as u32 % 32) as f64).to_value())
+                    }
+
+                    opcodes::PRINT => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("PRINT::stack::value".to_string()))?;
+
+                        let line = format!("{}\n", DisplayValue::new(&self.heap, &value));
+
+                        self.files.write(0, line.as_bytes())?;
+                    }
+
+                    opcodes::TOSTR => {
+                        pc += 1;
+
+                        let value = scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .pop()
+                            .ok_or_else(|| NoValueError("TOSTR::stack::value".to_string()))?;
+
+                        scope
+                            .as_mut()
+                            .ok_or_else(|| DeallocatedError("Scope::global".into()))?
+                            .push(&TeaStr(DisplayValue::new(&self.heap, &value).to_string()).to_value());
+                    }
+
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryIo;
+    use crate::test_util::{
+        array, bool, eval, function, get, jump, load, null, num, number, object, push, store, str,
+        string, value, vm,
+    };
+
+    /// Pushes each of `values` in turn.
+    fn push_all(values: &[Vec<u8>]) -> Vec<u8> {
+        values.iter().cloned().flat_map(push).collect()
+    }
+
+    /// Pushes `values`, runs `ops` and returns what is left on top of the stack formatted by
+    /// TOSTR, or `None` if that fails.
+    fn shown(values: &[Vec<u8>], ops: &[u8]) -> Option<String> {
+        string(&[push_all(values), ops.to_vec(), vec![opcodes::TOSTR]].concat()).ok()
+    }
+
+    fn text(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    fn binary(left: f64, right: f64, op: u8) -> Result<f64, VmError> {
+        number(&[push(num(left)), push(num(right)), vec![op]].concat())
+    }
+
+    #[test]
+    fn truncated_operands_are_errors() {
+        let ops = [
+            opcodes::STORE,
+            opcodes::LOAD,
+            opcodes::MARKER,
+            opcodes::GOTO,
+            opcodes::FORMAT,
+            opcodes::TONUM,
+            opcodes::PARSE_INT,
+            opcodes::OPEN,
+            opcodes::DECODE,
+        ];
+
+        for op in ops {
+            let code = [&push(num(1.))[..], &[op, 0, 0]].concat();
+
+            assert!(Vm::new().run(&code).is_err(), "opcode {op}");
+        }
+
+        for op in [opcodes::GOTO_IF, opcodes::GOTO_IF_NOT] {
+            for cond in [true, false] {
+                let code = [&push(value(ValueKind::Bool, &[cond as u8]))[..], &[op, 0]].concat();
+
+                assert!(Vm::new().run(&code).is_err(), "opcode {op}");
+            }
+        }
+
+        for op in [opcodes::GOTO_IF_NULL, opcodes::COALESCE] {
+            for pushed in [num(1.), value(ValueKind::Null, &[])] {
+                let code = [&push(pushed)[..], &[op, 0]].concat();
+
+                assert!(Vm::new().run(&code).is_err(), "opcode {op}");
+            }
+        }
+
+        let get = [push(object(&[])), vec![opcodes::GET, 0, 5, b'k']].concat();
+
+        assert!(Vm::new().run(&get).is_err());
+        assert!(Vm::new().run(&get[..get.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn malformed_values_are_errors() {
+        assert!(Vm::new().run(&[opcodes::PUSH]).is_err());
+        assert!(Vm::new().run(&[opcodes::PUSH, 200, 0, 0]).is_err());
+        assert!(Vm::new().run(&[opcodes::PUSH, ValueKind::Number as u8, 0]).is_err());
+        assert!(Vm::new().run(&[opcodes::PUSH, ValueKind::Number as u8, 0, 8, 0]).is_err());
+
+        let entry = [&1u16.to_be_bytes()[..], &1u16.to_be_bytes(), b"k", &[200, 0, 0, 0]].concat();
+        let object = push(value(ValueKind::Object, &entry));
+
+        assert!(Vm::new().run(&[object, get("k")].concat()).is_err());
+    }
+
+    #[test]
+    fn exit_status_is_only_kept_until_the_next_run() {
+        let mut vm = Vm::new();
+
+        vm.run(&[push(num(3.)), vec![opcodes::EXIT]].concat()).unwrap();
+        assert_eq!(vm.exit_status(), Some(3));
+
+        vm.run(&push(num(1.))).unwrap();
+        assert_eq!(vm.exit_status(), None);
+    }
+
+    #[test]
+    fn call_discards_what_the_function_left_on_its_stack() {
+        let callee = [push(num(1.)), push(num(2.)), vec![opcodes::RET, opcodes::PRINT]].concat();
+        let code = [push(num(5.)), push(function(&callee)), vec![opcodes::CALL]].concat();
+
+        assert_eq!(number(&code).ok(), Some(5.));
+
+        let mut vm = Vm::new();
+        let left = vm.call(&Value::from_bytes(&function(&callee)).unwrap(), &[]).unwrap();
+
+        assert_eq!(left.len(), 2);
+        assert_eq!(left[1].as_tea_number().ok().map(|n| n.value()), Some(2.));
+    }
+
+    #[test]
+    fn dynamic_keys_get_and_set_entries() {
+        let code = [
+            push(object(&[("a", num(1.))])),
+            store(200),
+            load(200),
+            push(str("b")),
+            push(num(2.)),
+            vec![opcodes::SET_DYN],
+            load(200),
+            push(str("b")),
+            vec![opcodes::GET_DYN],
+            load(200),
+            push(str("a")),
+            vec![opcodes::GET_DYN, opcodes::NSUB],
+        ];
+
+        assert_eq!(number(&code.concat()).ok(), Some(1.));
+
+        let missing = [push(object(&[])), push(str("a")), vec![opcodes::GET_DYN]];
+
+        assert!(eval(&missing.concat()).is_err());
+    }
+
+    #[test]
+    fn dynamic_keys_index_arrays() {
+        let items = array(&[num(1.), num(2.)]);
+        let code = [
+            push(items.clone()),
+            store(200),
+            load(200),
+            push_all(&[num(1.), num(5.)]),
+            vec![opcodes::SET_DYN],
+            load(200),
+            push(num(1.)),
+            vec![opcodes::GET_DYN],
+        ];
+
+        assert_eq!(number(&code.concat()).ok(), Some(5.));
+        let past_end = [push_all(&[items, num(2.), num(5.)]), vec![opcodes::SET_DYN]];
+
+        assert!(eval(&past_end.concat()).is_err());
+    }
+
+    #[test]
+    fn stack_opcodes_rearrange_the_top_of_the_stack() {
+        // NSUB leaves the second value minus the top one, which shows their order.
+        let run = |op: u8, pops: usize| {
+            let ops = [vec![op], vec![opcodes::POP; pops], vec![opcodes::NSUB]].concat();
+
+            number(&[push_all(&[num(1.), num(2.), num(4.)]), ops].concat()).ok()
+        };
+
+        assert_eq!(run(opcodes::DUP, 0), Some(0.));
+        assert_eq!(run(opcodes::POP, 0), Some(-1.));
+        assert_eq!(run(opcodes::SWAP, 0), Some(2.));
+        assert_eq!(run(opcodes::OVER, 0), Some(2.));
+        assert_eq!(run(opcodes::OVER, 1), Some(-2.));
+        assert_eq!(run(opcodes::ROT, 0), Some(3.));
+        assert_eq!(run(opcodes::ROT, 1), Some(-2.));
+    }
+
+    #[test]
+    fn stack_opcodes_need_enough_values() {
+        let needs = [
+            (opcodes::DUP, 1),
+            (opcodes::POP, 1),
+            (opcodes::SWAP, 2),
+            (opcodes::OVER, 2),
+            (opcodes::ROT, 3),
+        ];
+
+        for (op, needs) in needs {
+            let code = [push_all(&vec![num(1.); needs - 1]), vec![op]].concat();
+
+            assert!(eval(&code).is_err(), "opcode {op}");
+        }
+    }
+
+    #[test]
+    fn comparisons_push_booleans() {
+        let compare = |left: Vec<u8>, right: Vec<u8>, op: u8| shown(&[left, right], &[op]);
+
+        assert_eq!(compare(num(1.), num(2.), opcodes::NEQ), text("true"));
+        assert_eq!(compare(str("a"), str("a"), opcodes::NEQ), text("false"));
+        assert_eq!(compare(num(2.), num(2.), opcodes::NGE), text("true"));
+        assert_eq!(compare(num(1.), num(2.), opcodes::NGE), text("false"));
+        assert_eq!(compare(num(2.), num(2.), opcodes::NLE), text("true"));
+        assert_eq!(compare(num(3.), num(2.), opcodes::NLE), text("false"));
+        assert_eq!(compare(num(f64::NAN), num(f64::NAN), opcodes::NGE), text("false"));
+        assert_eq!(compare(str("b"), str("a"), opcodes::SGT), text("true"));
+        assert_eq!(compare(str("B"), str("a"), opcodes::SLT), text("true"));
+        assert_eq!(compare(str("é"), str("z"), opcodes::SGE), text("true"));
+        assert_eq!(compare(str("a"), str("a"), opcodes::SLE), text("true"));
+        assert_eq!(compare(num(1.), str("1"), opcodes::NGE), None);
+    }
+
+    #[test]
+    fn boolean_logic_takes_booleans() {
+        assert_eq!(shown(&[bool(true)], &[opcodes::NOT]), text("false"));
+        assert_eq!(shown(&[bool(true), bool(false)], &[opcodes::AND]), text("false"));
+        assert_eq!(shown(&[bool(true), bool(true)], &[opcodes::AND]), text("true"));
+        assert_eq!(shown(&[bool(false), bool(true)], &[opcodes::OR]), text("true"));
+        assert_eq!(shown(&[bool(false), bool(false)], &[opcodes::OR]), text("false"));
+        assert_eq!(shown(&[num(1.)], &[opcodes::NOT]), None);
+    }
+
+    #[test]
+    fn goto_if_not_jumps_on_false() {
+        let branch = |cond: bool| {
+            let code = [
+                push(bool(cond)),
+                jump(opcodes::GOTO_IF_NOT, 1),
+                push(str("then")),
+                jump(opcodes::GOTO, 2),
+                jump(opcodes::MARKER, 1),
+                push(str("else")),
+                jump(opcodes::MARKER, 2),
+            ];
+
+            string(&code.concat()).ok()
+        };
+
+        assert_eq!(branch(true), text("then"));
+        assert_eq!(branch(false), text("else"));
+    }
+
+    #[test]
+    fn bitwise_results_are_exact_32_bit_integers() {
+        assert_eq!(binary(1., 31., opcodes::SHL).ok(), Some(-2147483648.));
+        assert_eq!(binary(1., 63., opcodes::SHL).ok(), Some(-2147483648.));
+        assert_eq!(binary(1., 32., opcodes::SHL).ok(), Some(1.));
+        assert_eq!(binary(-8., 1., opcodes::SHR).ok(), Some(-4.));
+        assert_eq!(binary(-1., 0., opcodes::USHR).ok(), Some(4294967295.));
+        assert_eq!(binary(-8., 1., opcodes::USHR).ok(), Some(2147483644.));
+        assert_eq!(binary(1., 31., opcodes::ROTL).ok(), Some(-2147483648.));
+        assert_eq!(binary(1., 1., opcodes::ROTR).ok(), Some(-2147483648.));
+        assert_eq!(binary(0x1234_5678 as f64, 36., opcodes::ROTL).ok(), Some(0x2345_6781 as f64));
+        assert_eq!(number(&[push(num(0.)), vec![opcodes::BNOT]].concat()).ok(), Some(-1.));
+    }
+
+    #[test]
+    fn bitwise_operands_wrap_modulo_2_pow_32() {
+        assert_eq!(binary(4294967297., 0., opcodes::BOR).ok(), Some(1.));
+        assert_eq!(binary(2147483648., 0., opcodes::BXOR).ok(), Some(-2147483648.));
+        assert_eq!(binary(-4294967295., 3., opcodes::BAND).ok(), Some(1.));
+        assert_eq!(binary(1e300, 0., opcodes::BOR).ok(), Some(0.));
+    }
+
+    #[test]
+    fn bitwise_operands_must_be_whole_and_finite() {
+        assert!(binary(1.5, 1., opcodes::BAND).is_err());
+        assert!(binary(f64::INFINITY, 1., opcodes::BOR).is_err());
+        assert!(binary(1., f64::NAN, opcodes::SHL).is_err());
+    }
+
+    #[test]
+    fn string_opcodes_count_characters() {
+        let parts = array(&[str("a"), str("b")]);
+
+        assert_eq!(shown(&[str("a"), str("é")], &[opcodes::SCONCAT]), text("aé"));
+        assert_eq!(shown(&[str("aé😀")], &[opcodes::SLEN]), text("3"));
+        assert_eq!(shown(&[str("héllo"), num(1.), num(3.)], &[opcodes::SSLICE]), text("él"));
+        assert_eq!(shown(&[str("héllo"), num(3.), num(99.)], &[opcodes::SSLICE]), text("lo"));
+        assert_eq!(shown(&[str("aé"), num(1.)], &[opcodes::SCHAR_AT]), text("é"));
+        assert_eq!(shown(&[str("aé"), num(2.)], &[opcodes::SCHAR_AT]), None);
+        assert_eq!(shown(&[str("héllo"), str("l")], &[opcodes::SFIND]), text("2"));
+        assert_eq!(shown(&[str("héllo"), str("x")], &[opcodes::SFIND]), text("-1"));
+        assert_eq!(shown(&[str("a,b,"), str(",")], &[opcodes::SSPLIT]), text(r#"["a", "b", ""]"#));
+        assert_eq!(shown(&[str("aé"), str("")], &[opcodes::SSPLIT]), text(r#"["a", "é"]"#));
+        assert_eq!(shown(&[parts, str("-")], &[opcodes::SJOIN]), text("a-b"));
+        assert_eq!(shown(&[array(&[num(1.)]), str("-")], &[opcodes::SJOIN]), None);
+        assert_eq!(shown(&[str("héllo")], &[opcodes::SUPPER]), text("HÉLLO"));
+        assert_eq!(shown(&[str("HÉLLO")], &[opcodes::SLOWER]), text("héllo"));
+        assert_eq!(shown(&[str(" \ta b\n")], &[opcodes::STRIM]), text("a b"));
+    }
+
+    #[test]
+    fn tobool_is_false_only_for_empty_values() {
+        let empty_bytes = value(ValueKind::Bytes, &[]);
+
+        for value in [bool(false), null(), num(0.), num(f64::NAN), str(""), empty_bytes] {
+            assert_eq!(shown(&[value], &[opcodes::TOBOOL]), text("false"));
+        }
+
+        for value in [bool(true), num(-1.), str("0"), str("false"), array(&[]), object(&[])] {
+            assert_eq!(shown(&[value], &[opcodes::TOBOOL]), text("true"));
+        }
+    }
+
+    #[test]
+    fn tonum_and_parse_int_follow_their_mode() {
+        let tonum = |value: Vec<u8>, mode: u8| shown(&[value], &[opcodes::TONUM, mode]);
+        let parse_int = |s: &str, radix: f64| {
+            shown(&[str(s), num(radix)], &[opcodes::PARSE_INT, opcodes::ON_FAIL_RAISE])
+        };
+
+        assert_eq!(tonum(str(" 42\n"), opcodes::ON_FAIL_RAISE), text("42"));
+        assert_eq!(tonum(bool(true), opcodes::ON_FAIL_RAISE), text("1"));
+        assert_eq!(tonum(str("x"), opcodes::ON_FAIL_NULL), text("null"));
+        assert_eq!(tonum(str("x"), opcodes::ON_FAIL_RAISE), None);
+        assert_eq!(parse_int("ff", 16.), text("255"));
+        assert_eq!(parse_int("-101", 2.), text("-5"));
+        assert_eq!(parse_int("12", 37.), None);
+        assert_eq!(parse_int("1.5", 10.), None);
+    }
+
+    #[test]
+    fn null_is_not_false() {
+        assert_eq!(shown(&[null(), bool(false)], &[opcodes::EQ]), text("false"));
+        assert_eq!(shown(&[null(), null()], &[opcodes::EQ]), text("true"));
+        assert_eq!(shown(&[bool(false)], &[opcodes::ISNULL]), text("false"));
+        assert_eq!(shown(&[null()], &[opcodes::ISNULL]), text("true"));
+    }
+
+    #[test]
+    fn optional_opcodes_push_null_for_what_is_missing() {
+        let get_opt = [&[opcodes::GET_OPT][..], &1u16.to_be_bytes(), b"a"].concat();
+
+        let load_opt = jump(opcodes::LOAD_OPT, 200);
+
+        assert_eq!(string(&[load_opt, vec![opcodes::TOSTR]].concat()).ok(), text("null"));
+        assert_eq!(shown(&[object(&[])], &get_opt), text("null"));
+        assert_eq!(shown(&[array(&[]), num(0.)], &[opcodes::GET_DYN_OPT]), text("null"));
+        assert!(eval(&load(200)).is_err());
+
+        let coalesce = |value: Vec<u8>| {
+            let code = [
+                push(value),
+                jump(opcodes::COALESCE, 1),
+                push(str("fallback")),
+                jump(opcodes::MARKER, 1),
+            ];
+
+            string(&code.concat()).ok()
+        };
+
+        assert_eq!(coalesce(str("value")), text("value"));
+        assert_eq!(coalesce(null()), text("fallback"));
+    }
+
+    #[test]
+    fn natives_registered_in_an_object_are_called_from_bytecode() {
+        let mut vm = vm(&MemoryIo::new());
+        let call = |name: &str| [load(HOST_VAR), get(name), vec![opcodes::CALL]].concat();
+
+        vm.register_native_in(HOST_VAR, "twice", 1, |_vm, args| {
+            Ok(TeaNumber(args[0].as_tea_number()?.value() * 2.).to_value())
+        })
+        .unwrap();
+
+        vm.run(&[push(num(21.)), call("twice"), store(200)].concat()).unwrap();
+
+        let result = vm.get_global(200).map(|value| value.as_tea_number().map(|n| n.value()).ok());
+
+        assert_eq!(result, Some(Some(42.)));
+        assert!(vm.run(&call("twice")).is_err());
+        assert!(vm.register_native_in(200, "f", 0, |_vm, _args| Ok(TeaNull.to_value())).is_err());
+    }
+
+    #[test]
+    fn bytes_opcodes_count_bytes() {
+        let bytes = |items: &[f64]| array(&items.iter().map(|n| num(*n)).collect::<Vec<_>>());
+        let abc = || bytes(&[97., 98., 99.]);
+        let get = [opcodes::SWAP, opcodes::BYTES, opcodes::SWAP, opcodes::BYTES_GET];
+
+        let hi = bytes(&[104., 105., 0., 255.]);
+
+        assert_eq!(shown(&[hi], &[opcodes::BYTES]), text(r#"b"hi\x00\xff""#));
+        assert_eq!(shown(&[abc()], &[opcodes::BYTES, opcodes::BYTES_LEN]), text("3"));
+        assert_eq!(shown(&[abc(), num(2.)], &get), text("99"));
+        assert_eq!(shown(&[abc(), num(3.)], &get), None);
+        assert_eq!(shown(&[bytes(&[256.])], &[opcodes::BYTES]), None);
+        assert_eq!(shown(&[bytes(&[1.5])], &[opcodes::BYTES]), None);
+
+        let code = [
+            push(abc()),
+            vec![opcodes::BYTES],
+            push_all(&[num(1.), num(9.)]),
+            vec![opcodes::BYTES_SLICE],
+            push(abc()),
+            vec![opcodes::BYTES, opcodes::BYTES_CONCAT, opcodes::TOSTR],
+        ];
+
+        assert_eq!(string(&code.concat()).ok(), text(r#"b"bcabc""#));
+    }
+
+    #[test]
+    fn encode_and_decode_use_utf8() {
+        let invalid = || array(&[num(255.)]);
+        let round_trip = [opcodes::ENCODE, opcodes::DECODE, opcodes::ON_FAIL_RAISE];
+        let decode = |mode: u8| shown(&[invalid()], &[opcodes::BYTES, opcodes::DECODE, mode]);
+
+        assert_eq!(shown(&[str("é")], &[opcodes::ENCODE, opcodes::BYTES_LEN]), text("2"));
+        assert_eq!(shown(&[str("é")], &round_trip), text("é"));
+        assert_eq!(decode(opcodes::ON_FAIL_NULL), text("null"));
+        assert_eq!(decode(opcodes::ON_FAIL_RAISE), None);
+    }
+}
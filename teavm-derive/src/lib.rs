@@ -0,0 +1,357 @@
+//! `#[derive(IntoTea, FromTea)]` for `teavm-rs`, which re-exports both macros.
+//!
+//! Structs become objects with an entry per field, named after the field (or its position, for
+//! tuple structs). Enums become objects with a `type` entry naming the variant, alongside the
+//! variant's fields.
+//!
+//! Containers accept `#[tea(tag = "...")]` to name the variant entry of an enum. Variants accept
+//! `#[tea(rename = "...")]`. Fields accept:
+//!
+//! - `#[tea(rename = "...")]` to use another entry name.
+//! - `#[tea(readonly)]` or `#[tea(flags = N)]` to set the flags of the entry.
+//! - `#[tea(default)]` to use `Default::default()` when the entry is missing.
+//! - `#[tea(skip)]` to leave the field out, and use `Default::default()` when converting back.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Expr, Fields, Generics,
+    Ident, LitStr, Path, Result,
+};
+
+#[proc_macro_derive(IntoTea, attributes(tea))]
+pub fn derive_into_tea(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    into_tea(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(FromTea, attributes(tea))]
+pub fn derive_from_tea(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    from_tea(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    tag: Option<String>,
+}
+
+#[derive(Default)]
+struct VariantAttrs {
+    rename: Option<String>,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    flags: Option<Expr>,
+    default: bool,
+    skip: bool,
+}
+
+fn container_attrs(attrs: &[Attribute]) -> Result<ContainerAttrs> {
+    let mut out = ContainerAttrs::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tea")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                out.tag = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown container attribute, expected `tag`"))
+            }
+        })?;
+    }
+
+    Ok(out)
+}
+
+fn variant_attrs(attrs: &[Attribute]) -> Result<VariantAttrs> {
+    let mut out = VariantAttrs::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tea")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                out.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown variant attribute, expected `rename`"))
+            }
+        })?;
+    }
+
+    Ok(out)
+}
+
+fn field_attrs(attrs: &[Attribute]) -> Result<FieldAttrs> {
+    let mut out = FieldAttrs::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tea")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                out.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("readonly") {
+                out.flags = Some(parse_quote!(::teavm_rs::values::READONLY));
+            } else if meta.path.is_ident("flags") {
+                out.flags = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("default") {
+                out.default = true;
+            } else if meta.path.is_ident("skip") {
+                out.skip = true;
+            } else {
+                return Err(meta.error(
+                    "unknown field attribute, expected `rename`, `readonly`, `flags`, `default` or `skip`",
+                ));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(out)
+}
+
+/// A field of a struct or variant, with the binding it is destructured into.
+struct Field {
+    member: TokenStream2,
+    binding: Ident,
+    key: String,
+    attrs: FieldAttrs,
+}
+
+fn fields(fields: &Fields) -> Result<Vec<Field>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            let attrs = field_attrs(&field.attrs)?;
+
+            let (member, name) = match &field.ident {
+                Some(ident) => (quote!(#ident), ident.to_string()),
+                None => {
+                    let idx = syn::Index::from(idx);
+                    (quote!(#idx), idx.index.to_string())
+                }
+            };
+
+            Ok(Field {
+                member,
+                binding: format_ident!("__field{}", idx),
+                key: attrs.rename.clone().unwrap_or(name),
+                attrs,
+            })
+        })
+        .collect()
+}
+
+/// The pattern binding every field of `path`, or the expression building it from the bindings.
+fn pattern(path: &Path, fields: &[Field]) -> TokenStream2 {
+    let entries = fields.iter().map(|field| {
+        let (member, binding) = (&field.member, &field.binding);
+        quote!(#member: #binding)
+    });
+
+    quote!(#path { #(#entries),* })
+}
+
+/// Adds `bound` to every type parameter.
+fn bounded(generics: &Generics, bound: TokenStream2) -> Generics {
+    let mut generics = generics.clone();
+
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+
+    generics
+}
+
+/// Builds an object from the bindings of `fields`, starting with `tag` when it is given.
+fn into_object(fields: &[Field], tag: Option<(&str, &str)>) -> TokenStream2 {
+    let tag = tag.map(|(key, variant)| {
+        quote! {
+            entries.insert(
+                #key.to_string(),
+                (::teavm_rs::convert::IntoTea::into_tea(#variant, heap), 0u8),
+            );
+        }
+    });
+
+    let inserts = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+        let (key, binding) = (&field.key, &field.binding);
+        let flags = field.attrs.flags.clone().unwrap_or_else(|| parse_quote!(0u8));
+
+        quote! {
+            entries.insert(
+                #key.to_string(),
+                (::teavm_rs::convert::IntoTea::into_tea(#binding, heap), #flags),
+            );
+        }
+    });
+
+    quote! {{
+        let mut entries = ::teavm_rs::OrderedMap::new();
+        #tag
+        #(#inserts)*
+        ::teavm_rs::convert::object(heap, entries)
+    }}
+}
+
+/// Reads the bindings of `fields` from `entries`, reporting failures under `ty`.
+fn from_object(fields: &[Field], ty: &str) -> TokenStream2 {
+    let reads = fields.iter().map(|field| {
+        let (key, binding) = (&field.key, &field.binding);
+
+        let read = if field.attrs.skip {
+            quote!(::std::default::Default::default())
+        } else if field.attrs.default {
+            quote!(::teavm_rs::convert::field_or_default(heap, entries, #ty, #key)?)
+        } else {
+            quote!(::teavm_rs::convert::field(heap, entries, #ty, #key)?)
+        };
+
+        quote!(let #binding = #read;)
+    });
+
+    quote!(#(#reads)*)
+}
+
+fn into_tea(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let container = container_attrs(&input.attrs)?;
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let pattern = pattern(&parse_quote!(Self), &fields);
+            let object = into_object(&fields, None);
+
+            quote! {
+                let #pattern = self;
+                #object
+            }
+        }
+
+        Data::Enum(data) => {
+            let tag = container.tag.unwrap_or_else(|| "type".to_string());
+
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let rename = variant_attrs(&variant.attrs)?.rename;
+                    let variant_name = rename.unwrap_or_else(|| ident.to_string());
+                    let fields = fields(&variant.fields)?;
+                    let pattern = pattern(&parse_quote!(Self::#ident), &fields);
+                    let object = into_object(&fields, Some((&tag, &variant_name)));
+
+                    Ok(quote!(#pattern => #object,))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "IntoTea cannot be derived for unions",
+            ))
+        }
+    };
+
+    let generics = bounded(&input.generics, quote!(::teavm_rs::convert::IntoTea));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::teavm_rs::convert::IntoTea for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn into_tea(self, heap: &mut ::teavm_rs::heap::Heap) -> ::teavm_rs::values::Value {
+                #body
+            }
+        }
+    })
+}
+
+fn from_tea(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let ty = name.to_string();
+    let container = container_attrs(&input.attrs)?;
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let reads = from_object(&fields, &ty);
+            let construct = pattern(&parse_quote!(Self), &fields);
+
+            quote! {
+                #reads
+                Ok(#construct)
+            }
+        }
+
+        Data::Enum(data) => {
+            let tag = container.tag.unwrap_or_else(|| "type".to_string());
+
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let rename = variant_attrs(&variant.attrs)?.rename;
+                    let variant_name = rename.unwrap_or_else(|| ident.to_string());
+                    let fields = fields(&variant.fields)?;
+                    let reads = from_object(&fields, &format!("{ty}::{ident}"));
+                    let construct = pattern(&parse_quote!(Self::#ident), &fields);
+
+                    Ok(quote! {
+                        #variant_name => {
+                            #reads
+                            Ok(#construct)
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            quote! {
+                let variant: ::std::string::String =
+                    ::teavm_rs::convert::field(heap, entries, #ty, #tag)?;
+
+                match variant.as_str() {
+                    #(#arms)*
+                    other => Err(::teavm_rs::convert::unknown_variant(#ty, #tag, other)),
+                }
+            }
+        }
+
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "FromTea cannot be derived for unions",
+            ))
+        }
+    };
+
+    let generics = bounded(&input.generics, quote!(::teavm_rs::convert::FromTea));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::teavm_rs::convert::FromTea for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_tea(
+                heap: &::teavm_rs::heap::Heap,
+                value: &::teavm_rs::values::Value,
+            ) -> ::std::result::Result<Self, ::teavm_rs::VmError> {
+                let entries = ::teavm_rs::convert::entries(heap, value, #ty)?;
+                #body
+            }
+        }
+    })
+}